@@ -9,6 +9,7 @@ use space::Neighbor;
 use std::cell::RefCell;
 use std::io::Read;
 use std::path::PathBuf;
+use std::time::Instant;
 use structopt::StructOpt;
 
 struct Euclidean;
@@ -173,7 +174,7 @@ fn process<const M: usize, const M0: usize>(opt: &Opt) -> (Vec<f64>, Vec<f64>) {
                 }
             }
             // Get the worst distance
-            v.into_iter().take(opt.k).last().unwrap()
+            v.into_iter().take(opt.k).next_back().unwrap()
         })
         .collect();
     eprintln!("Done.");
@@ -202,13 +203,13 @@ fn process<const M: usize, const M0: usize>(opt: &Opt) -> (Vec<f64>, Vec<f64>) {
                 };
                 opt.k
             ];
-            let stats = easybench::bench_env(dest, |mut dest| {
+            let stats = easybench::bench_env(dest, |dest| {
                 let mut refmut = state.borrow_mut();
                 let (searcher, query) = &mut *refmut;
                 let (ix, query_feature) = query.next().unwrap();
                 let correct_worst_distance = correct_worst_distances[ix];
                 // Go through all the features.
-                for &mut neighbor in hnsw.nearest(&query_feature, ef, searcher, &mut dest) {
+                for &mut neighbor in hnsw.nearest(&query_feature, ef, searcher, dest) {
                     // Any feature that is less than or equal to the worst real nearest neighbor distance is correct.
                     if Euclidean.distance(&search_space[neighbor.index], &query_feature)
                         <= correct_worst_distance
@@ -230,6 +231,18 @@ fn process<const M: usize, const M0: usize>(opt: &Opt) -> (Vec<f64>, Vec<f64>) {
         );
     eprintln!("Done.");
 
+    let ef = opt.ending_ef;
+    let start = Instant::now();
+    #[cfg(feature = "rayon")]
+    let batch = hnsw.par_nearest_batch(&query_strings, opt.k, ef);
+    #[cfg(not(feature = "rayon"))]
+    let batch = hnsw.nearest_batch(&query_strings, opt.k, ef, &mut state.borrow_mut().0);
+    eprintln!(
+        "Batch throughput at ef = {}: {:.0} lookups per second",
+        ef,
+        batch.len() as f64 / start.elapsed().as_secs_f64()
+    );
+
     (recalls, times)
 }
 
@@ -0,0 +1,65 @@
+//! Deterministic synthetic datasets for benchmarking and testing.
+
+use alloc::{vec, vec::Vec};
+use rand_core::{RngCore, SeedableRng};
+use rand_pcg::Pcg64;
+
+/// Generates a 128-bit hamming space dataset with inlier queries, as used in the paper's benchmarks.
+///
+/// - `size` is the number of random 128-bit features in the search space.
+/// - `inliers` is the number of queries to generate. Each query is a copy of a feature chosen uniformly
+///   at random (with replacement) from the search space.
+/// - `bit_diff_prob` is the probability that each bit of a query is flipped from the chosen feature,
+///   which simulates the noise between two descriptors of the same point.
+/// - `seed` seeds a [`Pcg64`], so the same `seed` always produces the same dataset.
+///
+/// Returns the search space, the queries, and the hamming distance from each query to its true nearest
+/// neighbor in the search space. The ground truth is computed by linear search, so this takes time
+/// proportional to `size * inliers`.
+///
+/// # Panics
+///
+/// Panics if `size` is `0` and `inliers` is not.
+pub fn hamming_inliers(
+    size: usize,
+    inliers: usize,
+    bit_diff_prob: f64,
+    seed: u64,
+) -> (Vec<u128>, Vec<u128>, Vec<u32>) {
+    assert!(
+        size != 0 || inliers == 0,
+        "cannot generate inliers for an empty search space"
+    );
+    let mut prng = Pcg64::seed_from_u64(seed);
+
+    let search_space: Vec<u128> = (0..size).map(|_| random_u128(&mut prng)).collect();
+
+    let mut queries = vec![];
+    for _ in 0..inliers {
+        let mut query = search_space[(prng.next_u64() % size as u64) as usize];
+        for bit in 0..128 {
+            let uniform = prng.next_u64() as f64 / u64::MAX as f64;
+            if uniform < bit_diff_prob {
+                query ^= 1 << bit;
+            }
+        }
+        queries.push(query);
+    }
+
+    let ground_truth = queries
+        .iter()
+        .map(|&query| {
+            search_space
+                .iter()
+                .map(|&feature| (feature ^ query).count_ones())
+                .min()
+                .unwrap()
+        })
+        .collect();
+
+    (search_space, queries, ground_truth)
+}
+
+fn random_u128(prng: &mut impl RngCore) -> u128 {
+    (prng.next_u64() as u128) << 64 | prng.next_u64() as u128
+}
@@ -1,13 +1,18 @@
 use super::nodes::{HasNeighbors, Layer};
-use crate::hnsw::nodes::{NeighborNodes, Node};
+use crate::hnsw::nodes::{Dimensions, NeighborNodes, Node};
 use crate::*;
+use ahash::RandomState;
 use alloc::{vec, vec::Vec};
+use hashbrown::HashSet;
 use num_traits::Zero;
 use rand_core::{RngCore, SeedableRng};
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 use space::{Knn, KnnPoints, Metric, Neighbor};
 
+/// The number of items below which [`Params::auto_small_dataset`] caps the level of insertions.
+const SMALL_DATASET_SIZE: usize = 10000;
+
 /// This provides a HNSW implementation for any distance function.
 ///
 /// The type `T` must implement [`space::Metric`] to get implementations.
@@ -21,6 +26,11 @@ use space::{Knn, KnnPoints, Metric, Neighbor};
     ))
 )]
 pub struct Hnsw<Met, T, R, const M: usize, const M0: usize> {
+    /// Serializes `M` and `M0` so that deserializing into a type with different ones fails.
+    /// Data serialized before this was recorded skips the check.
+    #[cfg_attr(feature = "serde", serde(default))]
+    #[cfg_attr(not(feature = "serde"), allow(dead_code))]
+    dims: Dimensions<M, M0>,
     /// Contains the space metric.
     metric: Met,
     /// Contains the zero layer.
@@ -33,8 +43,125 @@ pub struct Hnsw<Met, T, R, const M: usize, const M0: usize> {
     layers: Vec<Vec<Node<M>>>,
     /// This needs to create resonably random outputs to determine the levels of insertions.
     prng: R,
+    /// The PRNG as it was when the HNSW was created, which [`Hnsw::clear`] restores.
+    /// This is `None` for HNSWs deserialized from data serialized before it was recorded.
+    #[cfg_attr(feature = "serde", serde(default))]
+    initial_prng: Option<R>,
     /// The parameters for the HNSW.
     params: Params,
+    /// Marks the items removed by [`Hnsw::soft_remove`] until they are compacted.
+    /// This is empty if there are none, and otherwise has an entry for every item.
+    #[cfg_attr(feature = "serde", serde(default))]
+    removed: Vec<bool>,
+}
+
+/// Summary statistics of the structure of an HNSW, returned by [`Hnsw::stats`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stats {
+    /// The number of layers, including the zero layer.
+    pub layers: usize,
+    /// The number of nodes on each level, starting with the zero layer.
+    pub layer_lens: Vec<usize>,
+    /// The smallest number of neighbors of a node on the zero layer, or `0` if it is empty.
+    pub zero_min_degree: usize,
+    /// The largest number of neighbors of a node on the zero layer, or `0` if it is empty.
+    pub zero_max_degree: usize,
+    /// The mean number of neighbors of a node on the zero layer, or `0.0` if it is empty.
+    pub zero_mean_degree: f64,
+    /// The item index of the entry point that searches start from, or `None` if the HNSW is empty.
+    pub entry_point: Option<usize>,
+}
+
+/// A structural problem found in an HNSW by [`Hnsw::validate`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GraphError {
+    /// The node at index `node` on `level` has a neighbor `neighbor` that does not exist on that level.
+    DanglingNeighbor {
+        level: usize,
+        node: usize,
+        neighbor: usize,
+    },
+    /// The zero layer has `items` nodes, but `features` features are stored.
+    FeatureCount { items: usize, features: usize },
+    /// The node at index `node` on `level` refers to an item `zero_node` that does not exist.
+    DanglingZeroNode {
+        level: usize,
+        node: usize,
+        zero_node: usize,
+    },
+    /// The node at index `node` on `level` has a `next_node` that is not the node of the same item on the level
+    /// below.
+    DanglingNextNode {
+        level: usize,
+        node: usize,
+        next_node: usize,
+    },
+    /// The item cannot be reached on the zero layer by following neighbors from the entry point.
+    Unreachable { item: usize },
+}
+
+/// The error raised when deserializing an HNSW into a type with different `M` or `M0` parameters
+/// than it was built with.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ParamsMismatch {
+    /// The `(M, M0)` parameters of the type being deserialized into.
+    pub expected: (usize, usize),
+    /// The `(M, M0)` parameters the HNSW was built with.
+    pub found: (usize, usize),
+}
+
+impl core::fmt::Display for ParamsMismatch {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "HNSW was built with M = {} and M0 = {}, but M = {} and M0 = {} were expected",
+            self.found.0, self.found.1, self.expected.0, self.expected.1
+        )
+    }
+}
+
+impl core::fmt::Display for GraphError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match *self {
+            GraphError::DanglingNeighbor {
+                level,
+                node,
+                neighbor,
+            } => write!(
+                f,
+                "node {} on level {} has neighbor {} which does not exist",
+                node, level, neighbor
+            ),
+            GraphError::FeatureCount { items, features } => write!(
+                f,
+                "the zero layer has {} items but {} features are stored",
+                items, features
+            ),
+            GraphError::DanglingZeroNode {
+                level,
+                node,
+                zero_node,
+            } => write!(
+                f,
+                "node {} on level {} refers to item {} which does not exist",
+                node, level, zero_node
+            ),
+            GraphError::DanglingNextNode {
+                level,
+                node,
+                next_node,
+            } => write!(
+                f,
+                "node {} on level {} refers to node {} on the level below which is not the same item",
+                node, level, next_node
+            ),
+            GraphError::Unreachable { item } => write!(
+                f,
+                "item {} is unreachable from the entry point on the zero layer",
+                item
+            ),
+        }
+    }
 }
 
 impl<Met, T, R, const M: usize, const M0: usize> Hnsw<Met, T, R, M, M0>
@@ -44,24 +171,47 @@ where
     /// Creates a new HNSW with a PRNG which is default seeded to produce deterministic behavior.
     pub fn new(metric: Met) -> Self {
         Self {
+            dims: Dimensions,
             metric,
             zero: vec![],
             features: vec![],
             layers: vec![],
             prng: R::from_seed(R::Seed::default()),
+            initial_prng: Some(R::from_seed(R::Seed::default())),
             params: Params::new(),
+            removed: vec![],
         }
     }
 
     /// Creates a new HNSW with a default seeded PRNG and with the specified params.
     pub fn new_params(metric: Met, params: Params) -> Self {
         Self {
+            dims: Dimensions,
             metric,
             zero: vec![],
             features: vec![],
             layers: vec![],
             prng: R::from_seed(R::Seed::default()),
+            initial_prng: Some(R::from_seed(R::Seed::default())),
             params,
+            removed: vec![],
+        }
+    }
+
+    /// Creates a new HNSW like [`Hnsw::new`] with room for `capacity` items before reallocating.
+    ///
+    /// See [`Hnsw::reserve`].
+    pub fn with_capacity(metric: Met, capacity: usize) -> Self {
+        Self {
+            dims: Dimensions,
+            metric,
+            zero: Vec::with_capacity(capacity),
+            features: Vec::with_capacity(capacity),
+            layers: vec![],
+            prng: R::from_seed(R::Seed::default()),
+            initial_prng: Some(R::from_seed(R::Seed::default())),
+            params: Params::new(),
+            removed: vec![],
         }
     }
 }
@@ -109,30 +259,48 @@ where
     Met: Metric<T>,
 {
     /// Creates a HNSW with the passed `prng`.
-    pub fn new_prng(metric: Met, prng: R) -> Self {
+    ///
+    /// The PRNG is only used to pick the level of each inserted item, so passing identically seeded PRNGs and
+    /// inserting the same features in the same order always builds an identical graph.
+    pub fn new_prng(metric: Met, prng: R) -> Self
+    where
+        R: Clone,
+    {
         Self {
+            dims: Dimensions,
             metric,
             zero: vec![],
             features: vec![],
             layers: vec![],
+            initial_prng: Some(prng.clone()),
             prng,
             params: Default::default(),
+            removed: vec![],
         }
     }
 
     /// Creates a HNSW with the passed `params` and `prng`.
-    pub fn new_params_and_prng(metric: Met, params: Params, prng: R) -> Self {
+    pub fn new_params_and_prng(metric: Met, params: Params, prng: R) -> Self
+    where
+        R: Clone,
+    {
         Self {
+            dims: Dimensions,
             metric,
             zero: vec![],
             features: vec![],
             layers: vec![],
+            initial_prng: Some(prng.clone()),
             prng,
             params,
+            removed: vec![],
         }
     }
 
     /// Inserts a feature into the HNSW.
+    ///
+    /// Returns the index of the new item, which is the index that searches return for it. Items are indexed in
+    /// insertion order starting at `0`.
     pub fn insert(&mut self, q: T, searcher: &mut Searcher<Met::Unit>) -> usize {
         // Get the level of this feature.
         let level = self.random_level();
@@ -168,7 +336,14 @@ where
         // Find the entry point on the level it was created by searching normally until its level.
         for ix in (level..self.layers.len()).rev() {
             // Perform an ANN search on this layer like normal.
-            self.search_single_layer(&q, searcher, Layer::NonZero(&self.layers[ix]), cap);
+            self.search_single_layer(
+                &q,
+                searcher,
+                Layer::NonZero(&self.layers[ix]),
+                cap,
+                None,
+                None,
+            );
             // Then lower the search only after we create the node.
             self.lower_search(&self.layers[ix], searcher);
             cap = if ix == level {
@@ -181,7 +356,14 @@ where
         // Then start from its level and connect it to its nearest neighbors.
         for ix in (0..core::cmp::min(level, self.layers.len())).rev() {
             // Perform an ANN search on this layer like normal.
-            self.search_single_layer(&q, searcher, Layer::NonZero(&self.layers[ix]), cap);
+            self.search_single_layer(
+                &q,
+                searcher,
+                Layer::NonZero(&self.layers[ix]),
+                cap,
+                None,
+                None,
+            );
             // Then use the results of that search on this layer to connect the nodes.
             self.create_node(&q, &searcher.nearest, ix + 1);
             // Then lower the search only after we create the node.
@@ -189,11 +371,15 @@ where
             cap = self.params.ef_construction;
         }
 
-        // Also search and connect the node to the zero layer.
-        self.search_zero_layer(&q, searcher, cap);
+        // Also search and connect the node to the zero layer. Soft removed items are kept as neighbors so that the
+        // graph stays connected through them until they are compacted.
+        self.search_single_layer(&q, searcher, Layer::Zero, cap, None, None);
         self.create_node(&q, &searcher.nearest, 0);
         // Add the feature to the zero layer.
         self.features.push(q);
+        if !self.removed.is_empty() {
+            self.removed.push(false);
+        }
 
         // Add all level vectors needed to be able to add this level.
         let zero_node = self.zero.len() - 1;
@@ -208,10 +394,118 @@ where
         zero_node
     }
 
+    /// Inserts a feature into the HNSW like [`Hnsw::insert`] and also finds its approximate `k` nearest neighbors
+    /// among the items that were already in the HNSW.
+    ///
+    /// If `ef` is no larger than [`Params::ef_construction`], the search done to connect the new item is reused,
+    /// so this costs the same as the insertion alone. Otherwise, another search is done with `ef`, excluding the
+    /// new item.
+    ///
+    /// Returns the index of the new item and its neighbors ordered from best to worst.
+    pub fn insert_and_query(
+        &mut self,
+        q: T,
+        k: usize,
+        ef: usize,
+        searcher: &mut Searcher<Met::Unit>,
+    ) -> (usize, Vec<Neighbor<Met::Unit>>) {
+        let was_empty = self.is_empty();
+        let item = self.insert(q, searcher);
+        if was_empty || k == 0 {
+            return (item, vec![]);
+        }
+
+        if ef <= self.params.ef_construction {
+            // The searcher still holds the zero layer search used to connect the item, which was done before
+            // the item was added.
+            let neighbors = searcher
+                .nearest
+                .iter()
+                .filter(|n| !self.is_removed(n.index))
+                .take(k)
+                .copied()
+                .collect();
+            (item, neighbors)
+        } else {
+            let mut neighbors = vec![
+                Neighbor {
+                    index: !0,
+                    distance: Met::Unit::zero(),
+                };
+                k
+            ];
+            let found = self
+                .nearest_filtered(
+                    &self.features[item],
+                    ef,
+                    searcher,
+                    |index| index != item,
+                    &mut neighbors,
+                )
+                .len();
+            neighbors.truncate(found);
+            (item, neighbors)
+        }
+    }
+
+    /// Inserts a feature into the HNSW like [`Hnsw::insert`] unless an item at distance zero from it is found.
+    ///
+    /// A search with [`Params::ef_construction`] is done first, and if the nearest item found is at distance
+    /// exactly zero, its index is returned and nothing is inserted. Items that are merely close are never merged.
+    /// Since the search is approximate, a duplicate can occasionally be missed, in which case the feature is
+    /// inserted as a new item. This costs one extra search per insertion.
+    ///
+    /// Returns the index of the existing duplicate or of the new item.
+    pub fn insert_dedup(&mut self, q: T, searcher: &mut Searcher<Met::Unit>) -> usize {
+        let mut neighbor = [Neighbor {
+            index: !0,
+            distance: Met::Unit::zero(),
+        }];
+        if let Some(n) = self
+            .nearest(&q, self.params.ef_construction, searcher, &mut neighbor)
+            .first()
+        {
+            if n.distance == Met::Unit::zero() {
+                return n.index;
+            }
+        }
+        self.insert(q, searcher)
+    }
+
+    /// Inserts every item of `other` into this HNSW, reusing the features stored in `other`.
+    ///
+    /// The items are reinserted in index order with this HNSW's params and PRNG, so the result is the same as
+    /// inserting the features of `other` one by one. Item `i` of `other` becomes item `offset + i` of this HNSW,
+    /// where `offset` is the number of items this HNSW had before merging, which is returned.
+    pub fn merge<R2, const M2: usize, const M02: usize>(
+        &mut self,
+        other: Hnsw<Met, T, R2, M2, M02>,
+        searcher: &mut Searcher<Met::Unit>,
+    ) -> usize {
+        let offset = self.len();
+        self.reserve(other.features.len());
+        for feature in other.features {
+            self.insert(feature, searcher);
+        }
+        for (ix, _) in other
+            .removed
+            .iter()
+            .enumerate()
+            .filter(|&(_, &removed)| removed)
+        {
+            self.soft_remove(offset + ix);
+        }
+        offset
+    }
+
     /// Does a k-NN search where `q` is the query element and it attempts to put up to `M` nearest neighbors into `dest`.
     /// `ef` is the candidate pool size. `ef` can be increased to get better recall at the expense of speed.
     /// If `ef` is less than `dest.len()` then `dest` will only be filled with `ef` elements.
     ///
+    /// Each neighbor contains both the item index and its distance to `q`, so the distance does not need
+    /// to be recomputed. The neighbors are ordered from best to worst, and neighbors at the same distance are
+    /// ordered by index. This is also the order of every other search method.
+    ///
     /// Returns a slice of the filled neighbors.
     pub fn nearest<'a>(
         &self,
@@ -223,35 +517,174 @@ where
         self.search_layer(q, ef, 0, searcher, dest)
     }
 
+    /// Performs the same search as [`Hnsw::nearest`], but returns the indices of up to `k` nearest items in a new
+    /// `Vec` rather than filling a slice.
+    ///
+    /// Fewer than `k` items are returned if fewer were found, which is always the case when there are fewer than
+    /// `k` items in the HNSW or `ef` is less than `k`. The items are ordered from best to worst.
+    pub fn nearest_k(
+        &self,
+        q: &T,
+        k: usize,
+        ef: usize,
+        searcher: &mut Searcher<Met::Unit>,
+    ) -> Vec<usize> {
+        self.nearest_vec(q, k, ef, searcher)
+            .into_iter()
+            .map(|n| n.index)
+            .collect()
+    }
+
+    /// Searches for the approximate `k` nearest neighbors of every query in `queries` with the given `ef`, reusing
+    /// the `searcher` for all of them.
+    ///
+    /// Returns the neighbors of each query in order, each ordered from best to worst.
+    pub fn nearest_batch(
+        &self,
+        queries: &[T],
+        k: usize,
+        ef: usize,
+        searcher: &mut Searcher<Met::Unit>,
+    ) -> Vec<Vec<Neighbor<Met::Unit>>> {
+        queries
+            .iter()
+            .map(|q| self.nearest_vec(q, k, ef, searcher))
+            .collect()
+    }
+
+    /// Performs the same searches as [`Hnsw::nearest_batch`], but searches for the queries in parallel using
+    /// rayon.
+    ///
+    /// Each rayon worker uses its own [`Searcher`].
+    #[cfg(feature = "rayon")]
+    pub fn par_nearest_batch(
+        &self,
+        queries: &[T],
+        k: usize,
+        ef: usize,
+    ) -> Vec<Vec<Neighbor<Met::Unit>>>
+    where
+        Self: Sync,
+        T: Sync,
+        Met::Unit: Send,
+    {
+        use rayon::prelude::*;
+
+        queries
+            .par_iter()
+            .map_init(Searcher::default, |searcher, q| {
+                self.nearest_vec(q, k, ef, searcher)
+            })
+            .collect()
+    }
+
+    /// Performs the same search as [`Hnsw::nearest`], returning up to `k` neighbors in a new `Vec`.
+    fn nearest_vec(
+        &self,
+        q: &T,
+        k: usize,
+        ef: usize,
+        searcher: &mut Searcher<Met::Unit>,
+    ) -> Vec<Neighbor<Met::Unit>> {
+        let mut neighbors = vec![
+            Neighbor {
+                index: !0,
+                distance: Met::Unit::zero(),
+            };
+            k
+        ];
+        let found = self.nearest(q, ef, searcher, &mut neighbors).len();
+        neighbors.truncate(found);
+        neighbors
+    }
+
     /// Extract the feature for a given item returned by [`HNSW::nearest`].
     ///
     /// The `item` must be retrieved from [`HNSW::search_layer`].
     pub fn feature(&self, item: usize) -> &T {
-        &self.features[item as usize]
+        &self.features[item]
+    }
+
+    /// Extract the feature for a given item, or `None` if there is no such item.
+    pub fn get(&self, item: usize) -> Option<&T> {
+        self.features.get(item)
+    }
+
+    /// Iterates over every item in the HNSW in index order, along with its feature.
+    pub fn features(&self) -> impl Iterator<Item = (usize, &T)> + '_ {
+        self.features.iter().enumerate()
     }
 
-    /// Extract the feature from a particular level for a given item returned by [`HNSW::search_layer`].
+    /// Extract the feature from a particular level for a given item returned by [`Hnsw::search_layer`].
     pub fn layer_feature(&self, level: usize, item: usize) -> &T {
-        &self.features[self.layer_item_id(level, item) as usize]
+        &self.features[self.layer_item_id(level, item)]
     }
 
-    /// Retrieve the item ID for a given layer item returned by [`HNSW::search_layer`].
+    /// Retrieve the item ID for a given layer item returned by [`Hnsw::search_layer`].
     pub fn layer_item_id(&self, level: usize, item: usize) -> usize {
         if level == 0 {
             item
         } else {
-            self.layers[level][item as usize].zero_node
+            self.layers[level - 1][item].zero_node
         }
     }
 
+    /// Returns the neighbors of an item on a particular level, borrowed from the graph.
+    ///
+    /// `item` is an item index, as returned by [`Hnsw::nearest`]. The returned neighbors are indices on that level,
+    /// like those returned by [`Hnsw::search_layer`], which can be mapped to item indices with
+    /// [`Hnsw::layer_item_id`]. On the zero level these are the item indices. Finding the item's node on a level
+    /// above the zero level takes time linear in the number of nodes on that level.
+    ///
+    /// Returns `None` if the item does not exist or does not reach that level.
+    pub fn neighbors(&self, item: usize, level: usize) -> Option<&[usize]> {
+        let node = if level == 0 {
+            if item >= self.zero.len() {
+                return None;
+            }
+            item
+        } else {
+            self.layers
+                .get(level - 1)?
+                .iter()
+                .position(|node| node.zero_node == item)?
+        };
+        Some(self.node_neighbors(level, node))
+    }
+
+    /// Returns the neighbors of the node at index `node` on a level.
+    fn node_neighbors(&self, level: usize, node: usize) -> &[usize] {
+        let neighbors: &[usize] = if level == 0 {
+            &self.zero[node].neighbors
+        } else {
+            &self.layers[level - 1][node].neighbors.neighbors
+        };
+        let len = neighbors
+            .iter()
+            .position(|&n| n == !0)
+            .unwrap_or(neighbors.len());
+        &neighbors[..len]
+    }
+
+    /// Returns the number of layers in the HNSW, including the zero layer.
+    ///
+    /// Valid levels range from `0` (the zero layer, which contains every item) to `layers() - 1`.
     pub fn layers(&self) -> usize {
         self.layers.len() + 1
     }
 
+    /// Returns the number of items in the HNSW.
+    ///
+    /// Item indices range from `0` to `len() - 1`.
     pub fn len(&self) -> usize {
         self.zero.len()
     }
 
+    /// Returns the number of nodes on a particular level, or `0` if the level does not exist.
+    ///
+    /// Every item on a level is also on all the levels below it, so this is also the number of items at or above
+    /// the level. With the default level distribution, each level is expected to have about `1 / M` times as many
+    /// nodes as the level below it.
     pub fn layer_len(&self, level: usize) -> usize {
         if level == 0 {
             self.features.len()
@@ -262,6 +695,7 @@ where
         }
     }
 
+    /// Returns `true` if the HNSW contains no items.
     pub fn is_empty(&self) -> bool {
         self.zero.is_empty()
     }
@@ -270,11 +704,101 @@ where
         self.layer_len(level) == 0
     }
 
+    /// Counts the nodes on a particular level of the HNSW by their number of neighbors (out-degree).
+    ///
+    /// Index `d` of the returned histogram is the number of nodes with exactly `d` neighbors. Nodes on the zero
+    /// level have at most `M0` neighbors and nodes on other levels at most `M`, so the histogram has `M0 + 1` or
+    /// `M + 1` entries respectively. Every entry is zero if the level does not exist.
+    pub fn degree_histogram(&self, level: usize) -> Vec<usize> {
+        let mut histogram = vec![0; if level == 0 { M0 + 1 } else { M + 1 }];
+        if level == 0 {
+            for node in &self.zero {
+                histogram[node.get_neighbors().count()] += 1;
+            }
+        } else if let Some(layer) = self.layers.get(level - 1) {
+            for node in layer {
+                histogram[node.get_neighbors().count()] += 1;
+            }
+        }
+        histogram
+    }
+
+    /// Computes summary statistics of the structure of the HNSW.
+    ///
+    /// This is useful to detect an under-connected graph when recall is poor, for instance a zero layer whose
+    /// minimum degree is far below `M0`. See [`Hnsw::degree_histogram`] for the full distribution of degrees.
+    pub fn stats(&self) -> Stats {
+        let degrees = self.zero.iter().map(|node| node.get_neighbors().count());
+        let total: usize = degrees.clone().sum();
+        Stats {
+            layers: self.layers(),
+            layer_lens: (0..self.layers())
+                .map(|level| self.layer_len(level))
+                .collect(),
+            zero_min_degree: degrees.clone().min().unwrap_or(0),
+            zero_max_degree: degrees.max().unwrap_or(0),
+            zero_mean_degree: if self.zero.is_empty() {
+                0.0
+            } else {
+                total as f64 / self.zero.len() as f64
+            },
+            entry_point: if self.zero.is_empty() {
+                None
+            } else {
+                Some(self.layers.last().map_or(0, |layer| layer[0].zero_node))
+            },
+        }
+    }
+
+    /// Writes a particular level of the HNSW as a directed Graphviz DOT graph for debugging.
+    ///
+    /// Each node is named by its index on the level and labeled with its item index, so it can be matched with
+    /// [`Hnsw::feature`]. Nothing is allocated, so this can be used on large levels. This takes a
+    /// [`core::fmt::Write`] since this crate is `no_std`, so pass a `String` or wrap an `std::io::Write`.
+    pub fn write_dot<W: core::fmt::Write>(&self, level: usize, w: &mut W) -> core::fmt::Result {
+        writeln!(w, "digraph level{} {{", level)?;
+        for node in 0..self.layer_len(level) {
+            writeln!(
+                w,
+                "    {} [label=\"{}\"];",
+                node,
+                self.layer_item_id(level, node)
+            )?;
+        }
+        for (from, to) in self.iter_layer_edges(level) {
+            writeln!(w, "    {} -> {};", from, to)?;
+        }
+        writeln!(w, "}}")
+    }
+
+    /// Iterates over every directed edge `(from, to)` on a particular level of the HNSW.
+    ///
+    /// The indices are unique on that level, like those returned by [`Hnsw::search_layer`], and can be converted
+    /// to item indices with [`Hnsw::layer_item_id`]. The edges of each node are yielded together, in the order
+    /// of the nodes. Nothing is yielded if the level does not exist.
+    pub fn iter_layer_edges(&self, level: usize) -> impl Iterator<Item = (usize, usize)> + '_ {
+        let zero = if level == 0 { &self.zero[..] } else { &[] };
+        let layer = match level.checked_sub(1).and_then(|ix| self.layers.get(ix)) {
+            Some(layer) => &layer[..],
+            None => &[],
+        };
+        let zero_edges = zero
+            .iter()
+            .enumerate()
+            .flat_map(|(from, node)| node.get_neighbors().map(move |to| (from, to)));
+        let layer_edges = layer
+            .iter()
+            .enumerate()
+            .flat_map(|(from, node)| node.get_neighbors().map(move |to| (from, to)));
+        zero_edges.chain(layer_edges)
+    }
+
     /// Performs the same algorithm as [`HNSW::nearest`], but stops on a particular layer of the network
     /// and returns the unique index on that layer rather than the item index.
     ///
     /// If this is passed a `level` of `0`, then this has the exact same functionality as [`HNSW::nearest`]
-    /// since the unique indices at layer `0` are the item indices.
+    /// since the unique indices at layer `0` are the item indices. Nodes of items removed by
+    /// [`Hnsw::soft_remove`] are never returned on any layer.
     pub fn search_layer<'a>(
         &self,
         q: &T,
@@ -292,156 +816,1104 @@ where
         let cap = 1;
 
         for (ix, layer) in self.layers.iter().enumerate().rev() {
-            self.search_single_layer(q, searcher, Layer::NonZero(layer), cap);
             if ix + 1 == level {
+                // Route through soft removed items like the zero layer search does, but never return them.
+                let live = |node: usize| !self.is_removed(layer[node].zero_node);
+                let filter = if self.removed.is_empty() {
+                    None
+                } else {
+                    Some(&live as &dyn Fn(usize) -> bool)
+                };
+                self.search_single_layer(q, searcher, Layer::NonZero(layer), cap, None, filter);
+                searcher.nearest.retain(|n| live(n.index));
                 let found = core::cmp::min(dest.len(), searcher.nearest.len());
-                dest.copy_from_slice(&searcher.nearest[..found]);
+                dest[..found].copy_from_slice(&searcher.nearest[..found]);
                 return &mut dest[..found];
             }
+            self.search_single_layer(q, searcher, Layer::NonZero(layer), cap, None, None);
             self.lower_search(layer, searcher);
         }
 
         let cap = ef;
 
         // search the zero layer
-        self.search_zero_layer(q, searcher, cap);
+        self.search_zero_layer(q, searcher, cap, None, None);
 
         let found = core::cmp::min(dest.len(), searcher.nearest.len());
-        dest.copy_from_slice(&searcher.nearest[..found]);
+        dest[..found].copy_from_slice(&searcher.nearest[..found]);
         &mut dest[..found]
     }
 
-    /// Greedily finds the approximate nearest neighbors to `q` in a non-zero layer.
-    /// This corresponds to Algorithm 2 in the paper.
-    fn search_single_layer(
+    /// Performs the same search as [`Hnsw::nearest`], but stops early once `expired` returns `true`, filling `dest`
+    /// with the best neighbors found so far.
+    ///
+    /// `expired` is called before visiting the neighbors of each candidate, so it should be cheap, and the search
+    /// overruns by at most one candidate's worth of distance computations. Since this crate is `no_std`, the
+    /// clock is left to the caller, for instance `|| Instant::now() >= deadline` with `std::time::Instant`.
+    ///
+    /// Only neighbors that were actually found are returned. If the HNSW is not empty, this always includes at
+    /// least one neighbor, even if `expired` is `true` from the start, unless the neighbors found were all removed
+    /// by [`Hnsw::soft_remove`].
+    pub fn nearest_deadline<'a>(
         &self,
         q: &T,
+        ef: usize,
+        mut expired: impl FnMut() -> bool,
         searcher: &mut Searcher<Met::Unit>,
-        layer: Layer<&[Node<M>]>,
-        cap: usize,
-    ) {
-        while let Some(Neighbor { index, .. }) = searcher.candidates.pop() {
-            for neighbor in match layer {
-                Layer::NonZero(layer) => layer[index as usize].get_neighbors(),
-                Layer::Zero => self.zero[index as usize].get_neighbors(),
-            } {
-                let node_to_visit = match layer {
-                    Layer::NonZero(layer) => layer[neighbor as usize].zero_node,
-                    Layer::Zero => neighbor,
-                };
+        dest: &'a mut [Neighbor<Met::Unit>],
+    ) -> &'a mut [Neighbor<Met::Unit>] {
+        // If there is nothing in here, then just return nothing.
+        if self.features.is_empty() {
+            return &mut [];
+        }
 
-                // Don't visit previously visited things. We use the zero node to allow reusing the seen filter
-                // across all layers since zero nodes are consistent among all layers.
-                // TODO: Use Cuckoo Filter or Bloom Filter to speed this up/take less memory.
-                if searcher.seen.insert(node_to_visit) {
-                    // Compute the distance of this neighbor.
-                    let distance = self
-                        .metric
-                        .distance(q, &self.features[node_to_visit as usize]);
-                    // Attempt to insert into nearest queue.
-                    let pos = searcher.nearest.partition_point(|n| n.distance <= distance);
-                    if pos != cap {
-                        // It was successful. Now we need to know if its full.
-                        if searcher.nearest.len() == cap {
-                            // In this case remove the worst item.
-                            searcher.nearest.pop();
-                        }
-                        // Either way, add the new item.
-                        let candidate = Neighbor {
-                            index: neighbor as usize,
-                            distance,
-                        };
-                        searcher.nearest.insert(pos, candidate);
-                        searcher.candidates.push(candidate);
-                    }
-                }
-            }
+        self.initialize_searcher(q, searcher);
+        for layer in self.layers.iter().rev() {
+            self.search_single_layer_until(
+                q,
+                searcher,
+                Layer::NonZero(layer),
+                1,
+                None,
+                None,
+                &mut expired,
+            );
+            self.lower_search(layer, searcher);
         }
-    }
+        self.search_zero_layer_until(q, searcher, ef, None, None, &mut expired);
 
-    /// Greedily finds the approximate nearest neighbors to `q` in the zero layer.
-    fn search_zero_layer(&self, q: &T, searcher: &mut Searcher<Met::Unit>, cap: usize) {
-        self.search_single_layer(q, searcher, Layer::Zero, cap);
+        let found = core::cmp::min(dest.len(), searcher.nearest.len());
+        dest[..found].copy_from_slice(&searcher.nearest[..found]);
+        &mut dest[..found]
     }
 
-    /// Ready a search for the next level down.
+    /// Finds every item within `radius` of `q`, regardless of how many there are.
     ///
-    /// `m` is the maximum number of nearest neighbors to consider during the search.
-    fn lower_search(&self, layer: &[Node<M>], searcher: &mut Searcher<Met::Unit>) {
-        // Clear the candidates so we can fill them with the best nodes in the last layer.
-        searcher.candidates.clear();
-        // Only preserve the best candidate. The original paper's algorithm uses `1` every time.
-        // See Algorithm 5 line 5 of the paper. The paper makes no further comment on why `1` was chosen.
-        let &Neighbor { index, distance } = searcher.nearest.first().unwrap();
-        searcher.nearest.clear();
-        // Update the node to the next layer.
-        let new_index = layer[index].next_node as usize;
-        let candidate = Neighbor {
-            index: new_index,
-            distance,
-        };
-        // Insert the index of the nearest neighbor into the nearest pool for the next layer.
-        searcher.nearest.push(candidate);
-        // Insert the index into the candidate pool as well.
-        searcher.candidates.push(candidate);
-    }
+    /// The search descends the layers exactly like [`Hnsw::nearest`], but in the zero layer every item found
+    /// within `radius` is kept rather than only the `ef` nearest. `ef` still controls how many items outside
+    /// the radius are kept to route the search, so increasing it improves recall at the expense of speed.
+    ///
+    /// Returns the neighbors within the radius ordered from best to worst.
+    pub fn nearest_within(
+        &self,
+        q: &T,
+        radius: Met::Unit,
+        ef: usize,
+        searcher: &mut Searcher<Met::Unit>,
+    ) -> Vec<Neighbor<Met::Unit>> {
+        // If there is nothing in here, then just return nothing.
+        if self.features.is_empty() {
+            return vec![];
+        }
 
-    /// Resets a searcher, but does not set the `cap` on the nearest neighbors.
-    /// Must be passed the query element `q`.
-    fn initialize_searcher(&self, q: &T, searcher: &mut Searcher<Met::Unit>) {
-        // Clear the searcher.
-        searcher.clear();
-        // Add the entry point.
-        let entry_distance = self.metric.distance(q, self.entry_feature());
-        let candidate = Neighbor {
-            index: 0,
-            distance: entry_distance,
-        };
-        searcher.candidates.push(candidate);
-        searcher.nearest.push(candidate);
-        searcher.seen.insert(
-            self.layers
-                .last()
-                .map(|layer| layer[0].zero_node)
-                .unwrap_or(0),
-        );
+        self.descend(q, searcher);
+        self.search_zero_layer(q, searcher, ef, Some(radius), None);
+
+        let found = searcher.nearest.partition_point(|n| n.distance <= radius);
+        searcher.nearest[..found].to_vec()
     }
 
-    /// Gets the entry point's feature.
-    fn entry_feature(&self) -> &T {
-        if let Some(last_layer) = self.layers.last() {
-            &self.features[last_layer[0].zero_node as usize]
-        } else {
-            &self.features[0]
+    /// Performs the same search as [`Hnsw::nearest`], but only returns items for which `filter` returns `true`.
+    ///
+    /// The search still routes through the items that fail the filter, so the graph stays connected, but only
+    /// the items that pass it count towards `ef`. When the filter is very selective, the passing items may be
+    /// far apart in the graph and recall drops, so increase `ef` to compensate. Checking the filter makes each
+    /// step of the search cost time proportional to `ef`, and a filter that rejects almost every item degrades
+    /// towards visiting the whole graph.
+    ///
+    /// Returns a slice of the filled neighbors, ordered from best to worst.
+    pub fn nearest_filtered<'a, F>(
+        &self,
+        q: &T,
+        ef: usize,
+        searcher: &mut Searcher<Met::Unit>,
+        filter: F,
+        dest: &'a mut [Neighbor<Met::Unit>],
+    ) -> &'a mut [Neighbor<Met::Unit>]
+    where
+        F: Fn(usize) -> bool,
+    {
+        // If there is nothing in here, then just return nothing.
+        if self.features.is_empty() {
+            return &mut [];
         }
-    }
 
-    /// Generates a correctly distributed random level as per Algorithm 1 line 4 of the paper.
-    fn random_level(&mut self) -> usize {
-        let uniform: f64 = self.prng.next_u64() as f64 / core::u64::MAX as f64;
-        (-libm::log(uniform) * libm::log(M as f64).recip()) as usize
+        self.descend(q, searcher);
+        self.search_zero_layer(q, searcher, ef, None, Some(&filter));
+
+        let mut found = 0;
+        for (d, &s) in dest
+            .iter_mut()
+            .zip(searcher.nearest.iter().filter(|n| filter(n.index)))
+        {
+            *d = s;
+            found += 1;
+        }
+        &mut dest[..found]
     }
 
-    /// Creates a new node at a layer given its nearest neighbors in that layer.
-    /// This contains Algorithm 3 from the paper, but also includes some additional logic.
-    fn create_node(&mut self, q: &T, nearest: &[Neighbor<Met::Unit>], layer: usize) {
-        if layer == 0 {
-            let new_index = self.zero.len();
-            let mut neighbors: [usize; M0] = [!0; M0];
-            for (d, s) in neighbors.iter_mut().zip(nearest.iter()) {
-                *d = s.index as usize;
+    /// Performs the same search as [`Hnsw::nearest`], but collapses items with identical features into the one
+    /// with the lowest index.
+    ///
+    /// Identical features are always at the same distance from `q`, so each item is only compared with the kept
+    /// items at the same distance. This costs at most one feature comparison per kept item for each item found
+    /// by the search, and usually far fewer, but it can be expensive if many items are at the same distance.
+    ///
+    /// Returns a slice of the filled neighbors, ordered from best to worst.
+    pub fn nearest_dedup<'a>(
+        &self,
+        q: &T,
+        ef: usize,
+        searcher: &mut Searcher<Met::Unit>,
+        dest: &'a mut [Neighbor<Met::Unit>],
+    ) -> &'a mut [Neighbor<Met::Unit>]
+    where
+        T: PartialEq,
+    {
+        // If there is nothing in here, then just return nothing.
+        if self.features.is_empty() {
+            return &mut [];
+        }
+
+        self.descend(q, searcher);
+        self.search_zero_layer(q, searcher, ef, None, None);
+
+        let mut found = 0;
+        for &neighbor in &searcher.nearest {
+            // Once `dest` is full, only items at the same distance as the worst kept item can still matter.
+            if found == dest.len()
+                && matches!(dest.last(), Some(worst) if neighbor.distance > worst.distance)
+            {
+                break;
             }
+            let duplicate = dest[..found]
+                .iter_mut()
+                .rev()
+                .take_while(|kept| kept.distance == neighbor.distance)
+                .find(|kept| self.features[kept.index] == self.features[neighbor.index]);
+            if let Some(kept) = duplicate {
+                kept.index = core::cmp::min(kept.index, neighbor.index);
+            } else if found < dest.len() {
+                dest[found] = neighbor;
+                found += 1;
+            }
+        }
+        &mut dest[..found]
+    }
+
+    /// Finds the distance from `q` to its approximate `k`-th nearest neighbor (where `k = 1` is the nearest).
+    ///
+    /// This is useful for density estimation, where only the distance is needed. `ef` is raised to `k` if it is
+    /// smaller. Returns `None` if `k` is `0` or if fewer than `k` neighbors were found, which is always the case
+    /// for an empty HNSW or one with fewer than `k` items.
+    pub fn kth_distance(
+        &self,
+        q: &T,
+        k: usize,
+        ef: usize,
+        searcher: &mut Searcher<Met::Unit>,
+    ) -> Option<Met::Unit> {
+        if k == 0 || self.features.is_empty() {
+            return None;
+        }
+
+        self.descend(q, searcher);
+        self.search_zero_layer(q, searcher, core::cmp::max(ef, k), None, None);
+        searcher.nearest.get(k - 1).map(|n| n.distance)
+    }
+
+    /// Finds the approximate distance from every item to its nearest other item, in index order.
+    ///
+    /// This searches for every item with the given `ef`, excluding the item itself, so it costs as much as
+    /// `len()` queries. This is useful for outlier detection. An item whose feature was also inserted under
+    /// another index has a distance of zero. Items for which no other item is found, such as the only item
+    /// in an HNSW, get `None`. Items removed by [`Hnsw::soft_remove`] also get `None`, and are never the
+    /// nearest other item of another item.
+    pub fn nn_distances(
+        &self,
+        ef: usize,
+        searcher: &mut Searcher<Met::Unit>,
+    ) -> Vec<Option<Met::Unit>> {
+        (0..self.len())
+            .map(|item| self.nn_distance(item, ef, searcher))
+            .collect()
+    }
+
+    /// Performs the same computation as [`Hnsw::nn_distances`], but searches for the items in parallel using
+    /// rayon.
+    ///
+    /// Each rayon worker uses its own [`Searcher`].
+    #[cfg(feature = "rayon")]
+    pub fn par_nn_distances(&self, ef: usize) -> Vec<Option<Met::Unit>>
+    where
+        Self: Sync,
+        Met::Unit: Send,
+    {
+        use rayon::prelude::*;
+
+        (0..self.len())
+            .into_par_iter()
+            .map_init(Searcher::default, |searcher, item| {
+                self.nn_distance(item, ef, searcher)
+            })
+            .collect()
+    }
+
+    /// Finds the approximate distance from an item to its nearest other item.
+    fn nn_distance(
+        &self,
+        item: usize,
+        ef: usize,
+        searcher: &mut Searcher<Met::Unit>,
+    ) -> Option<Met::Unit> {
+        if self.is_removed(item) {
+            return None;
+        }
+        let mut neighbor = [Neighbor {
+            index: !0,
+            distance: Met::Unit::zero(),
+        }];
+        self.nearest_filtered(
+            &self.features[item],
+            ef,
+            searcher,
+            |index| index != item,
+            &mut neighbor,
+        )
+        .first()
+        .map(|n| n.distance)
+    }
+
+    /// Finds the approximate `k` nearest neighbors of `q` without having to choose `ef`.
+    ///
+    /// The search is repeated with `ef` starting at `k` and doubling each time, until the `k` nearest neighbors
+    /// found are the same set for two successive values of `ef` or `ef` reaches `max_ef`. Each repetition is a
+    /// full search, so in the worst case this performs about `log2(max_ef / k) + 1` searches, the last with
+    /// `ef = max_ef`, which costs about twice as much as a single search with `ef = max_ef`. Queries that
+    /// converge quickly only cost a few searches with a small `ef`.
+    ///
+    /// Returns up to `k` neighbors ordered from best to worst.
+    pub fn nearest_stable(
+        &self,
+        q: &T,
+        k: usize,
+        max_ef: usize,
+        searcher: &mut Searcher<Met::Unit>,
+    ) -> Vec<Neighbor<Met::Unit>> {
+        // If there is nothing in here, then just return nothing.
+        if k == 0 || self.features.is_empty() {
+            return vec![];
+        }
+
+        let mut ef = k;
+        let mut previous = vec![];
+        loop {
+            self.descend(q, searcher);
+            self.search_zero_layer(q, searcher, ef, None, None);
+            searcher.nearest.truncate(k);
+
+            // Compare the set of items rather than their order, since items at equal distances may be reordered.
+            let mut current: Vec<usize> = searcher.nearest.iter().map(|n| n.index).collect();
+            current.sort_unstable();
+            if current == previous || ef >= max_ef {
+                return searcher.nearest.clone();
+            }
+            previous = current;
+            ef = core::cmp::min(ef * 2, max_ef);
+        }
+    }
+
+    /// Finds the approximate nearest neighbor of `q` like [`Hnsw::nearest`], then verifies it against every item
+    /// within two hops of it in the zero layer, returning the best item found.
+    ///
+    /// The result is never worse than the nearest neighbor found by [`Hnsw::nearest`] with the same `ef`, and
+    /// it is improved when the true nearest neighbor is close to the approximate one in the graph but was missed
+    /// by the search. This costs up to `M0 * M0` extra distance computations on top of the search, although items
+    /// already visited by the search are skipped, so it is most useful with a small `ef`.
+    ///
+    /// Returns `None` if the HNSW is empty or the search only found items removed by [`Hnsw::soft_remove`].
+    pub fn nearest_verified(
+        &self,
+        q: &T,
+        ef: usize,
+        searcher: &mut Searcher<Met::Unit>,
+    ) -> Option<Neighbor<Met::Unit>> {
+        // If there is nothing in here, then just return nothing.
+        if self.features.is_empty() {
+            return None;
+        }
+
+        self.descend(q, searcher);
+        self.search_zero_layer(q, searcher, core::cmp::max(ef, 1), None, None);
+
+        // Every item the search has seen is no better than the best one it found, so only unseen items are checked.
+        let approximate = *searcher.nearest.first()?;
+        let mut best = approximate;
+        for neighbor in self.zero[approximate.index].get_neighbors() {
+            for item in core::iter::once(neighbor).chain(self.zero[neighbor].get_neighbors()) {
+                if searcher.seen.insert(item) && !self.is_removed(item) {
+                    let distance = self.metric.distance(q, &self.features[item]);
+                    if distance < best.distance {
+                        best = Neighbor {
+                            index: item,
+                            distance,
+                        };
+                    }
+                }
+            }
+        }
+        Some(best)
+    }
+
+    /// Removes an item from the HNSW and returns its feature.
+    ///
+    /// The item is unlinked from every layer it appears in. Every node that had an edge to it loses that edge
+    /// and is offered the removed node's neighbors in its place, keeping them if it has room or if they are
+    /// closer than its current worst neighbor. If the item was the entry point, another node from the top layer
+    /// becomes the entry point.
+    ///
+    /// Like [`Vec::swap_remove`], the item with the highest index is moved into the index of the removed item,
+    /// so that is the only other item whose index changes. This takes time linear in the size of the HNSW since
+    /// every node is checked for edges to the removed item.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `item` is out of bounds.
+    pub fn remove(&mut self, item: usize) -> T {
+        assert!(item < self.len(), "item index out of bounds");
+        let last = self.len() - 1;
+        if !self.removed.is_empty() {
+            self.removed.swap_remove(item);
+            if !self.removed.contains(&true) {
+                self.removed.clear();
+            }
+        }
+
+        // Remove the item from every non-zero layer it appears in, starting from the top.
+        for ix in (0..self.layers.len()).rev() {
+            if let Some(node) = self.layers[ix].iter().position(|n| n.zero_node == item) {
+                self.unlink(ix + 1, node);
+                let moved = self.layers[ix].len() - 1;
+                self.layers[ix].swap_remove(node);
+                if node != moved {
+                    // The moved node is now at the removed node's index, so update everything pointing to it.
+                    for other in &mut self.layers[ix] {
+                        other.neighbors.rename(moved, node);
+                    }
+                    if let Some(above) = self.layers.get_mut(ix + 1) {
+                        for other in above.iter_mut().filter(|n| n.next_node == moved) {
+                            other.next_node = node;
+                        }
+                    }
+                }
+            }
+        }
+        // Layers above this item's level are subsets of it, so only the top layers can have become empty.
+        while matches!(self.layers.last(), Some(layer) if layer.is_empty()) {
+            self.layers.pop();
+        }
+
+        // Remove the item from the zero layer.
+        self.unlink(0, item);
+        self.zero.swap_remove(item);
+        let feature = self.features.swap_remove(item);
+        if item != last {
+            // The last item is now at the removed item's index, so update everything pointing to it.
+            for node in &mut self.zero {
+                node.rename(last, item);
+            }
+            for layer in &mut self.layers {
+                for node in layer.iter_mut().filter(|n| n.zero_node == last) {
+                    node.zero_node = item;
+                }
+            }
+            if let Some(first) = self.layers.first_mut() {
+                for node in first.iter_mut().filter(|n| n.next_node == last) {
+                    node.next_node = item;
+                }
+            }
+        }
+        feature
+    }
+
+    /// Marks an item as removed without changing the graph, so that searches no longer return it.
+    ///
+    /// This is much cheaper than [`Hnsw::remove`], which takes time linear in the size of the HNSW. Searches still
+    /// route through soft removed items, so the graph stays connected and recall is unaffected, but each step of
+    /// the zero layer search costs time proportional to `ef` while any remain. Their indices and features are
+    /// kept, so [`Hnsw::len`] and [`Hnsw::feature`] still include them, until [`Hnsw::compact`] is called.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `item` is out of bounds.
+    pub fn soft_remove(&mut self, item: usize) {
+        assert!(item < self.len(), "item index out of bounds");
+        self.removed.resize(self.len(), false);
+        self.removed[item] = true;
+    }
+
+    /// Returns `true` if the item was removed by [`Hnsw::soft_remove`] and has not been compacted yet.
+    pub fn is_removed(&self, item: usize) -> bool {
+        matches!(self.removed.get(item), Some(true))
+    }
+
+    /// Removes every item marked by [`Hnsw::soft_remove`] from the graph, returning how many were removed.
+    ///
+    /// Every node that had an edge to a removed node loses that edge and is offered the removed node's remaining
+    /// neighbors in its place, as in [`Hnsw::remove`]. The remaining items keep their relative order, so indices
+    /// change exactly as if [`Vec::retain`] was called, which can be mirrored to keep external data in sync with
+    /// the item indices. This takes time linear in the size of the HNSW, no matter how many items are removed.
+    pub fn compact(&mut self) -> usize {
+        if self.removed.is_empty() {
+            return 0;
+        }
+        let removed = core::mem::take(&mut self.removed);
+
+        // Repair the edges around the removed nodes on every layer before any indices change.
+        let mut gone = removed.clone();
+        self.repair_removed(0, &gone);
+        for level in 1..self.layers() {
+            gone.clear();
+            gone.extend(self.layers[level - 1].iter().map(|n| removed[n.zero_node]));
+            self.repair_removed(level, &gone);
+        }
+
+        // Map the old index of every remaining node on each layer to its new index.
+        let zero_remap = retained_indices(removed.iter().copied());
+        let mut below_remap = zero_remap.clone();
+        for layer in &mut self.layers {
+            let remap = retained_indices(layer.iter().map(|n| removed[n.zero_node]));
+            layer.retain(|n| !removed[n.zero_node]);
+            for node in layer.iter_mut() {
+                node.zero_node = zero_remap[node.zero_node];
+                node.next_node = below_remap[node.next_node];
+                for n in &mut node.neighbors.neighbors {
+                    if *n != !0 {
+                        *n = remap[*n];
+                    }
+                }
+            }
+            below_remap = remap;
+        }
+        // Layers above an item's level are subsets of it, so only the top layers can have become empty.
+        while matches!(self.layers.last(), Some(layer) if layer.is_empty()) {
+            self.layers.pop();
+        }
+
+        let mut ix = 0;
+        self.zero.retain(|_| {
+            ix += 1;
+            !removed[ix - 1]
+        });
+        for node in &mut self.zero {
+            for n in &mut node.neighbors {
+                if *n != !0 {
+                    *n = zero_remap[*n];
+                }
+            }
+        }
+        let mut ix = 0;
+        self.features.retain(|_| {
+            ix += 1;
+            !removed[ix - 1]
+        });
+        removed.iter().filter(|&&removed| removed).count()
+    }
+
+    /// Removes every edge that points to a node that does not exist on its layer, returning how many were removed.
+    ///
+    /// Only items that have both a zero layer node and a feature are kept as neighbors. A node on an upper layer
+    /// whose `next_node` does not refer to its item on the layer below is pointed back at it, and a node whose item
+    /// is missing on the layer below is dropped along with its edges.
+    ///
+    /// An HNSW built by this crate never contains such edges, so this is only a safety net for graphs that were
+    /// deserialized or imported from a mismatched source, which would otherwise panic when searched.
+    pub fn prune_invalid_edges(&mut self) -> usize {
+        let len = self.zero.len().min(self.features.len());
+        let mut pruned = 0;
+        for node in &mut self.zero {
+            pruned += node.retain(|n| n < len);
+        }
+
+        // The item of every node on the layer below, or `!0` for nodes that were dropped.
+        let mut below = (0..self.zero.len())
+            .map(|item| if item < len { item } else { !0 })
+            .collect::<Vec<_>>();
+        let mut below_remap = (0..self.zero.len()).collect::<Vec<_>>();
+        for layer in &mut self.layers {
+            let gone = layer
+                .iter_mut()
+                .map(|node| {
+                    if node.zero_node >= len {
+                        return true;
+                    }
+                    if below.get(node.next_node) != Some(&node.zero_node) {
+                        match below.iter().position(|&item| item == node.zero_node) {
+                            Some(next_node) => node.next_node = next_node,
+                            None => return true,
+                        }
+                    }
+                    false
+                })
+                .collect::<Vec<_>>();
+            let remap = retained_indices(gone.iter().copied());
+            below = layer
+                .iter()
+                .zip(&gone)
+                .map(|(node, &gone)| if gone { !0 } else { node.zero_node })
+                .collect();
+
+            let mut ix = 0;
+            layer.retain(|node| {
+                ix += 1;
+                if gone[ix - 1] {
+                    pruned += node.get_neighbors().count();
+                }
+                !gone[ix - 1]
+            });
+            for node in layer.iter_mut() {
+                node.next_node = below_remap[node.next_node];
+                pruned += node
+                    .neighbors
+                    .retain(|n| matches!(remap.get(n), Some(&n) if n != !0));
+                for n in &mut node.neighbors.neighbors {
+                    if *n != !0 {
+                        *n = remap[*n];
+                    }
+                }
+            }
+            below_remap = remap;
+        }
+        // Layers above an item's level are subsets of it, so only the top layers can have become empty.
+        while matches!(self.layers.last(), Some(layer) if layer.is_empty()) {
+            self.layers.pop();
+        }
+        pruned
+    }
+
+    /// Checks the structure of the HNSW, returning the first problem found.
+    ///
+    /// Every item must have a feature, every neighbor on every layer must be a node that exists on that layer, and
+    /// every node on an upper layer must refer to an existing item and to the node of that item on the level below.
+    /// Every item must also be reachable on the zero layer by following neighbors from the entry point, since a
+    /// search can never return an item it cannot reach. This walks the whole graph, so it is meant for debugging
+    /// and testing rather than for use alongside searches.
+    pub fn validate(&self) -> Result<(), GraphError> {
+        let len = self.zero.len();
+        if self.features.len() != len {
+            return Err(GraphError::FeatureCount {
+                items: len,
+                features: self.features.len(),
+            });
+        }
+        for (node, neighbors) in self.zero.iter().enumerate() {
+            if let Some(neighbor) = neighbors.get_neighbors().find(|&n| n >= len) {
+                return Err(GraphError::DanglingNeighbor {
+                    level: 0,
+                    node,
+                    neighbor,
+                });
+            }
+        }
+        for (ix, layer) in self.layers.iter().enumerate() {
+            for (node, neighbors) in layer.iter().enumerate() {
+                if let Some(neighbor) = neighbors.get_neighbors().find(|&n| n >= layer.len()) {
+                    return Err(GraphError::DanglingNeighbor {
+                        level: ix + 1,
+                        node,
+                        neighbor,
+                    });
+                }
+                if neighbors.zero_node >= len {
+                    return Err(GraphError::DanglingZeroNode {
+                        level: ix + 1,
+                        node,
+                        zero_node: neighbors.zero_node,
+                    });
+                }
+                let below = if ix == 0 {
+                    self.zero
+                        .get(neighbors.next_node)
+                        .map(|_| neighbors.next_node)
+                } else {
+                    self.layers[ix - 1]
+                        .get(neighbors.next_node)
+                        .map(|below| below.zero_node)
+                };
+                if below != Some(neighbors.zero_node) {
+                    return Err(GraphError::DanglingNextNode {
+                        level: ix + 1,
+                        node,
+                        next_node: neighbors.next_node,
+                    });
+                }
+            }
+        }
+
+        if len == 0 {
+            return Ok(());
+        }
+        let entry = self.layers.last().map_or(0, |layer| layer[0].zero_node);
+        let mut reached = vec![false; len];
+        reached[entry] = true;
+        let mut stack = vec![entry];
+        while let Some(node) = stack.pop() {
+            for neighbor in self.zero[node].get_neighbors() {
+                if !reached[neighbor] {
+                    reached[neighbor] = true;
+                    stack.push(neighbor);
+                }
+            }
+        }
+        match reached.iter().position(|&reached| !reached) {
+            Some(item) => Err(GraphError::Unreachable { item }),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the approximate number of heap bytes used by the HNSW.
+    ///
+    /// This counts the allocated capacity of the features and of the neighbor lists on every layer, not just the
+    /// part in use. It does not include any heap memory owned by the features themselves (such as the contents of
+    /// a `Vec` feature), by the metric, or by the PRNG.
+    pub fn memory_footprint(&self) -> usize {
+        use core::mem::size_of;
+
+        self.features.capacity() * size_of::<T>()
+            + self.removed.capacity() * size_of::<bool>()
+            + self.zero.capacity() * size_of::<NeighborNodes<M0>>()
+            + self.layers.capacity() * size_of::<Vec<Node<M>>>()
+            + self
+                .layers
+                .iter()
+                .map(|layer| layer.capacity() * size_of::<Node<M>>())
+                .sum::<usize>()
+    }
+
+    /// Reserves room for at least `additional` more items to be inserted before reallocating.
+    ///
+    /// This only reserves the zero layer and the features, which make up nearly all of the memory. It is purely
+    /// a performance hint and does not change the graph that is built.
+    pub fn reserve(&mut self, additional: usize) {
+        self.zero.reserve(additional);
+        self.features.reserve(additional);
+    }
+
+    /// Removes every item from the HNSW, keeping the allocated memory of the zero layer and the features.
+    ///
+    /// These make up nearly all of the memory of the HNSW, so rebuilding the HNSW after this reuses it.
+    /// The params are kept and the PRNG is reset to its state when the HNSW was created, so inserting the same
+    /// features again builds the same graph as a fresh HNSW would.
+    pub fn clear(&mut self)
+    where
+        R: Clone,
+    {
+        if let Some(prng) = &self.initial_prng {
+            self.prng = prng.clone();
+        }
+        self.zero.clear();
+        self.features.clear();
+        self.layers.clear();
+        self.removed.clear();
+    }
+
+    /// Computes the recall of a `k`-NN search with the given `ef` for every query in `queries`.
+    ///
+    /// `ground_truth[i]` must contain the item indices of the true nearest neighbors of `queries[i]`,
+    /// from best to worst. Only the first `k` of them are considered. The recall of a query is the
+    /// fraction of those true nearest neighbors that were found by [`Hnsw::nearest`].
+    ///
+    /// This makes it possible to look at the distribution and worst cases of recall rather than just
+    /// the average, which is given by [`Hnsw::recall_at_k`].
+    pub fn per_query_recall(
+        &self,
+        queries: &[T],
+        ground_truth: &[Vec<usize>],
+        k: usize,
+        ef: usize,
+        searcher: &mut Searcher<Met::Unit>,
+    ) -> Vec<f64> {
+        assert_eq!(
+            queries.len(),
+            ground_truth.len(),
+            "each query must have a ground truth"
+        );
+        let mut dest = vec![
+            Neighbor {
+                index: !0,
+                distance: Met::Unit::zero(),
+            };
+            k
+        ];
+        queries
+            .iter()
+            .zip(ground_truth)
+            .map(|(q, truth)| {
+                let truth = &truth[..core::cmp::min(k, truth.len())];
+                // There is nothing to find, so nothing was missed.
+                if truth.is_empty() {
+                    return 1.0;
+                }
+                let found = self.nearest(q, ef, searcher, &mut dest);
+                let hits = truth
+                    .iter()
+                    .filter(|&&index| found.iter().any(|n| n.index == index))
+                    .count();
+                hits as f64 / truth.len() as f64
+            })
+            .collect()
+    }
+
+    /// Computes the mean recall of a `k`-NN search with the given `ef` over all `queries`.
+    ///
+    /// See [`Hnsw::per_query_recall`] for the format of `ground_truth`.
+    pub fn recall_at_k(
+        &self,
+        queries: &[T],
+        ground_truth: &[Vec<usize>],
+        k: usize,
+        ef: usize,
+        searcher: &mut Searcher<Met::Unit>,
+    ) -> f64 {
+        if queries.is_empty() {
+            return 1.0;
+        }
+        let recalls = self.per_query_recall(queries, ground_truth, k, ef, searcher);
+        recalls.iter().sum::<f64>() / recalls.len() as f64
+    }
+
+    /// Finds the smallest `ef` for which the 1-NN recall over `queries` is at least `target_recall`.
+    ///
+    /// `ground_truth[i]` must be the item index of the true nearest neighbor of `queries[i]`. A query counts
+    /// as a hit if the neighbor found is that item or is at the same distance from the query as that item, so
+    /// ties in the ground truth do not count as misses.
+    ///
+    /// `ef` is doubled starting from `1` until the target is met, then the smallest passing `ef` is found by a
+    /// binary search between the last failing and the first passing value. This assumes that recall does not
+    /// decrease as `ef` increases, which holds on average but not always, so the result should be treated as a
+    /// good starting point rather than an exact threshold. If the target is not met even with `ef` equal to the
+    /// number of items, that number is returned.
+    pub fn tune_ef(
+        &self,
+        queries: &[T],
+        ground_truth: &[usize],
+        target_recall: f64,
+        searcher: &mut Searcher<Met::Unit>,
+    ) -> usize {
+        assert_eq!(
+            queries.len(),
+            ground_truth.len(),
+            "each query must have a ground truth"
+        );
+        let max_ef = core::cmp::max(self.features.len(), 1);
+        let mut dest = [Neighbor {
+            index: !0,
+            distance: Met::Unit::zero(),
+        }];
+        let mut meets_target = |ef: usize| {
+            if queries.is_empty() {
+                return true;
+            }
+            let hits = queries
+                .iter()
+                .zip(ground_truth)
+                .filter(|&(q, &truth)| {
+                    let truth_distance = self.metric.distance(q, &self.features[truth]);
+                    matches!(
+                        self.nearest(q, ef, searcher, &mut dest).first(),
+                        Some(n) if n.index == truth || n.distance <= truth_distance
+                    )
+                })
+                .count();
+            hits as f64 / queries.len() as f64 >= target_recall
+        };
+
+        // Double `ef` until the target is met, keeping track of the largest `ef` known to fail.
+        let mut failing = 0;
+        let mut passing = 1;
+        while !meets_target(passing) {
+            if passing >= max_ef {
+                return max_ef;
+            }
+            failing = passing;
+            passing = core::cmp::min(passing * 2, max_ef);
+        }
+
+        // Binary search for the smallest passing `ef` in `(failing, passing]`.
+        while passing - failing > 1 {
+            let mid = failing + (passing - failing) / 2;
+            if meets_target(mid) {
+                passing = mid;
+            } else {
+                failing = mid;
+            }
+        }
+        passing
+    }
+
+    /// Greedily finds the approximate nearest neighbors to `q` in a non-zero layer.
+    /// This corresponds to Algorithm 2 in the paper.
+    ///
+    /// If a `radius` is passed, every neighbor within the radius is kept in addition to the `cap` nearest.
+    ///
+    /// If a `filter` is passed, only the neighbors that pass it count towards `cap`. The neighbors that fail it
+    /// are still kept ahead of the worst counted neighbor so that the search can route through them.
+    fn search_single_layer(
+        &self,
+        q: &T,
+        searcher: &mut Searcher<Met::Unit>,
+        layer: Layer<&[Node<M>]>,
+        cap: usize,
+        radius: Option<Met::Unit>,
+        filter: Option<&dyn Fn(usize) -> bool>,
+    ) {
+        self.search_single_layer_until(q, searcher, layer, cap, radius, filter, &mut || false);
+    }
+
+    /// Performs the same search as [`Hnsw::search_single_layer`], but stops before visiting the neighbors of the
+    /// next candidate once `expired` returns `true`, leaving the best neighbors found so far in the nearest pool.
+    #[allow(clippy::too_many_arguments)]
+    fn search_single_layer_until(
+        &self,
+        q: &T,
+        searcher: &mut Searcher<Met::Unit>,
+        layer: Layer<&[Node<M>]>,
+        cap: usize,
+        radius: Option<Met::Unit>,
+        filter: Option<&dyn Fn(usize) -> bool>,
+        expired: &mut dyn FnMut() -> bool,
+    ) {
+        let within = |distance| matches!(radius, Some(radius) if distance <= radius);
+        // Whether a neighbor counts towards `cap`.
+        let is_counted = |index: usize| !matches!(filter, Some(filter) if !filter(index));
+        // The number of neighbors in the nearest pool that count towards `cap`.
+        let mut counted = searcher
+            .nearest
+            .iter()
+            .filter(|n| is_counted(n.index))
+            .count();
+        while let Some(Neighbor { index, .. }) = searcher.candidates.pop() {
+            if expired() {
+                searcher.candidates.clear();
+                break;
+            }
+            searcher.visited += 1;
+            for neighbor in match layer {
+                Layer::NonZero(layer) => layer[index].get_neighbors(),
+                Layer::Zero => self.zero[index].get_neighbors(),
+            } {
+                let node_to_visit = match layer {
+                    Layer::NonZero(layer) => layer[neighbor].zero_node,
+                    Layer::Zero => neighbor,
+                };
+
+                // Don't visit previously visited things. We use the zero node so that the seen filter
+                // uses the same indices on every layer.
+                // TODO: Use Cuckoo Filter or Bloom Filter to speed this up/take less memory.
+                if searcher.seen.insert(node_to_visit) {
+                    // Compute the distance of this neighbor.
+                    let distance = self.metric.distance(q, &self.features[node_to_visit]);
+                    searcher.distance_computations += 1;
+                    // Attempt to insert into nearest queue. Ties are ordered by index so that results are
+                    // deterministic and do not depend on the order the graph was traversed in.
+                    let pos = searcher
+                        .nearest
+                        .partition_point(|n| (n.distance, n.index) < (distance, neighbor));
+                    // It is kept if fewer than `cap` counted items are ahead of it. The pruning below ensures
+                    // that fewer than `cap` counted items are ahead of the worst item unless every item is within
+                    // the radius, so that is the case whenever it is not last or there are fewer than `cap` in total.
+                    if within(distance)
+                        || cap != 0 && (counted < cap || pos < searcher.nearest.len())
+                    {
+                        // It was successful, so add the new item.
+                        let candidate = Neighbor {
+                            index: neighbor,
+                            distance,
+                        };
+                        searcher.nearest.insert(pos, candidate);
+                        searcher.candidates.push(candidate);
+                        counted += is_counted(neighbor) as usize;
+                        // Now remove the worst items while there are already `cap` counted items ahead of them.
+                        // Items within the radius are never removed, so it can grow past `cap`.
+                        while let Some(worst) = searcher.nearest.last() {
+                            let worst_counted = is_counted(worst.index) as usize;
+                            if within(worst.distance) || counted - worst_counted < cap {
+                                break;
+                            }
+                            searcher.nearest.pop();
+                            counted -= worst_counted;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Greedily finds the approximate nearest neighbors to `q` in the zero layer.
+    ///
+    /// Soft removed items are routed through like items that fail the `filter`, and are then dropped from the
+    /// nearest pool.
+    fn search_zero_layer(
+        &self,
+        q: &T,
+        searcher: &mut Searcher<Met::Unit>,
+        cap: usize,
+        radius: Option<Met::Unit>,
+        filter: Option<&dyn Fn(usize) -> bool>,
+    ) {
+        self.search_zero_layer_until(q, searcher, cap, radius, filter, &mut || false);
+    }
+
+    /// Performs the same search as [`Hnsw::search_zero_layer`], but stops early once `expired` returns `true`.
+    fn search_zero_layer_until(
+        &self,
+        q: &T,
+        searcher: &mut Searcher<Met::Unit>,
+        cap: usize,
+        radius: Option<Met::Unit>,
+        filter: Option<&dyn Fn(usize) -> bool>,
+        expired: &mut dyn FnMut() -> bool,
+    ) {
+        if self.removed.is_empty() {
+            self.search_single_layer_until(q, searcher, Layer::Zero, cap, radius, filter, expired);
+            return;
+        }
+        let live = |index: usize| {
+            !self.removed[index] && !matches!(filter, Some(filter) if !filter(index))
+        };
+        self.search_single_layer_until(q, searcher, Layer::Zero, cap, radius, Some(&live), expired);
+        searcher.nearest.retain(|n| !self.removed[n.index]);
+    }
+
+    /// Searches every non-zero layer, leaving the searcher ready to search the zero layer.
+    ///
+    /// Must not be called on an empty HNSW.
+    fn descend(&self, q: &T, searcher: &mut Searcher<Met::Unit>) {
+        self.initialize_searcher(q, searcher);
+        for layer in self.layers.iter().rev() {
+            self.search_single_layer(q, searcher, Layer::NonZero(layer), 1, None, None);
+            self.lower_search(layer, searcher);
+        }
+    }
+
+    /// Ready a search for the next level down.
+    ///
+    /// `m` is the maximum number of nearest neighbors to consider during the search.
+    fn lower_search(&self, layer: &[Node<M>], searcher: &mut Searcher<Met::Unit>) {
+        // Clear the candidates so we can fill them with the best nodes in the last layer.
+        searcher.candidates.clear();
+        // Only preserve the best candidate. The original paper's algorithm uses `1` every time.
+        // See Algorithm 5 line 5 of the paper. The paper makes no further comment on why `1` was chosen.
+        let &Neighbor { index, distance } = searcher.nearest.first().unwrap();
+        searcher.nearest.clear();
+        // Each layer is searched with its own seen set, as per Algorithm 2 line 1 of the paper, so that items
+        // seen on the layer above can still be found on this layer.
+        searcher.seen.clear();
+        searcher.seen.insert(layer[index].zero_node);
+        // Update the node to the next layer.
+        let new_index = layer[index].next_node;
+        let candidate = Neighbor {
+            index: new_index,
+            distance,
+        };
+        // Insert the index of the nearest neighbor into the nearest pool for the next layer.
+        searcher.nearest.push(candidate);
+        // Insert the index into the candidate pool as well.
+        searcher.candidates.push(candidate);
+    }
+
+    /// Resets a searcher, but does not set the `cap` on the nearest neighbors.
+    /// Must be passed the query element `q`.
+    fn initialize_searcher(&self, q: &T, searcher: &mut Searcher<Met::Unit>) {
+        // Clear the searcher.
+        searcher.clear();
+        // Add the entry point.
+        let entry_distance = self.metric.distance(q, self.entry_feature());
+        searcher.distance_computations += 1;
+        let candidate = Neighbor {
+            index: 0,
+            distance: entry_distance,
+        };
+        searcher.candidates.push(candidate);
+        searcher.nearest.push(candidate);
+        searcher.seen.insert(
+            self.layers
+                .last()
+                .map(|layer| layer[0].zero_node)
+                .unwrap_or(0),
+        );
+    }
+
+    /// Gets the entry point's feature.
+    fn entry_feature(&self) -> &T {
+        if let Some(last_layer) = self.layers.last() {
+            &self.features[last_layer[0].zero_node]
+        } else {
+            &self.features[0]
+        }
+    }
+
+    /// Generates a correctly distributed random level as per Algorithm 1 line 4 of the paper.
+    fn random_level(&mut self) -> usize {
+        let uniform: f64 = self.prng.next_u64() as f64 / u64::MAX as f64;
+        let level_multiplier = self
+            .params
+            .level_multiplier
+            .unwrap_or_else(|| libm::log(M as f64).recip());
+        let mut level = (-libm::log(uniform) * level_multiplier) as usize;
+        if let Some(max_level) = self.params.max_level {
+            level = core::cmp::min(level, max_level);
+        }
+        if self.params.auto_small_dataset && self.len() < SMALL_DATASET_SIZE {
+            // Cap the level at the highest level that is expected to contain at least `M` items,
+            // since a layer with fewer items than that cannot fill even a single neighbor list.
+            let max_level = (libm::log((self.len() + 1) as f64 / M as f64) / libm::log(M as f64))
+                .max(0.0) as usize;
+            level = core::cmp::min(level, max_level);
+        }
+        level
+    }
+
+    /// Creates a new node at a layer given its nearest neighbors in that layer.
+    /// This contains Algorithm 3 from the paper, but also includes some additional logic.
+    fn create_node(&mut self, q: &T, nearest: &[Neighbor<Met::Unit>], layer: usize) {
+        let selected = if self.params.heuristic {
+            Some(self.select_neighbors_heuristic(
+                q,
+                nearest,
+                layer,
+                if layer == 0 { M0 } else { M },
+                self.params.extend_candidates,
+            ))
+        } else {
+            None
+        };
+        // Without the heuristic, the nearest candidates are the neighbors.
+        let fill = |neighbors: &mut [usize]| match &selected {
+            Some(selected) => {
+                for (d, &s) in neighbors.iter_mut().zip(selected.iter()) {
+                    *d = s;
+                }
+            }
+            None => {
+                for (d, s) in neighbors.iter_mut().zip(nearest.iter()) {
+                    *d = s.index;
+                }
+            }
+        };
+        if layer == 0 {
+            let new_index = self.zero.len();
+            let mut neighbors: [usize; M0] = [!0; M0];
+            fill(&mut neighbors);
             let node = NeighborNodes { neighbors };
             for neighbor in node.get_neighbors() {
-                self.add_neighbor(q, new_index as usize, neighbor, layer);
+                self.add_neighbor(q, new_index, neighbor, layer);
             }
             self.zero.push(node);
         } else {
             let new_index = self.layers[layer - 1].len();
             let mut neighbors: [usize; M] = [!0; M];
-            for (d, s) in neighbors.iter_mut().zip(nearest.iter()) {
-                *d = s.index;
-            }
+            fill(&mut neighbors);
             let node = Node {
                 zero_node: self.zero.len(),
                 next_node: if layer == 1 {
@@ -458,6 +1930,69 @@ where
         }
     }
 
+    /// Selects up to `m` neighbors for `q` from the `nearest` candidates in a layer, ordered from best to worst.
+    /// This is Algorithm 4 from the paper. The candidates are only extended with their neighbors if `extend` is set.
+    fn select_neighbors_heuristic(
+        &self,
+        q: &T,
+        nearest: &[Neighbor<Met::Unit>],
+        layer: usize,
+        m: usize,
+        extend: bool,
+    ) -> Vec<usize> {
+        let feature = |node: usize| {
+            &self.features[if layer == 0 {
+                node
+            } else {
+                self.layers[layer - 1][node].zero_node
+            }]
+        };
+
+        let mut candidates = nearest.to_vec();
+        if extend {
+            let mut included: HashSet<usize, RandomState> =
+                HashSet::with_hasher(RandomState::with_seeds(0, 0, 0, 0));
+            included.extend(nearest.iter().map(|n| n.index));
+            for n in nearest {
+                let neighbors = if layer == 0 {
+                    self.zero[n.index].get_neighbors()
+                } else {
+                    self.layers[layer - 1][n.index].get_neighbors()
+                };
+                for neighbor in neighbors {
+                    if included.insert(neighbor) {
+                        candidates.push(Neighbor {
+                            index: neighbor,
+                            distance: self.metric.distance(q, feature(neighbor)),
+                        });
+                    }
+                }
+            }
+            candidates.sort_by_key(|c| c.distance);
+        }
+
+        let mut selected: Vec<usize> = Vec::with_capacity(m);
+        let mut discarded = vec![];
+        for candidate in candidates {
+            if selected.len() == m {
+                break;
+            }
+            // Only keep the candidate if it is closer to `q` than to every neighbor selected so far.
+            if selected.iter().all(|&s| {
+                candidate.distance < self.metric.distance(feature(candidate.index), feature(s))
+            }) {
+                selected.push(candidate.index);
+            } else if self.params.keep_pruned {
+                discarded.push(candidate.index);
+            }
+        }
+        if self.params.keep_pruned {
+            let remaining = m - selected.len();
+            selected.extend(discarded.into_iter().take(remaining));
+        }
+        selected
+    }
+
     /// Attempts to add a neighbor to a target node.
     fn add_neighbor(&mut self, q: &T, node_ix: usize, target_ix: usize, layer: usize) {
         // Get the feature for the target and get the neighbor slice for the target.
@@ -481,11 +2016,9 @@ where
             // In this case we did find the first spot where the target was empty within the slice.
             // Now we add the neighbor to this slot.
             if layer == 0 {
-                self.zero[target_ix as usize].neighbors[empty_point] = node_ix;
+                self.zero[target_ix].neighbors[empty_point] = node_ix;
             } else {
-                self.layers[layer - 1][target_ix as usize]
-                    .neighbors
-                    .neighbors[empty_point] = node_ix;
+                self.layers[layer - 1][target_ix].neighbors.neighbors[empty_point] = node_ix;
             }
         } else {
             // Otherwise, we need to find the worst neighbor currently.
@@ -517,15 +2050,182 @@ where
             // This is also different for the zero layer.
             if self.metric.distance(q, target_feature) < worst_distance {
                 if layer == 0 {
-                    self.zero[target_ix as usize].neighbors[worst_ix] = node_ix;
+                    self.zero[target_ix].neighbors[worst_ix] = node_ix;
                 } else {
-                    self.layers[layer - 1][target_ix as usize]
-                        .neighbors
-                        .neighbors[worst_ix] = node_ix;
+                    self.layers[layer - 1][target_ix].neighbors.neighbors[worst_ix] = node_ix;
                 }
             }
         }
     }
+
+    /// Removes every edge to `node` in a layer.
+    ///
+    /// Each node that loses its edge is offered the neighbors of `node` in its place.
+    fn unlink(&mut self, layer: usize, node: usize) {
+        let (len, orphaned) = if layer == 0 {
+            (
+                self.zero.len(),
+                self.zero[node].get_neighbors().collect::<Vec<_>>(),
+            )
+        } else {
+            let layer = &self.layers[layer - 1];
+            (layer.len(), layer[node].get_neighbors().collect::<Vec<_>>())
+        };
+
+        for target in (0..len).filter(|&target| target != node) {
+            let removed = if layer == 0 {
+                self.zero[target].remove(node)
+            } else {
+                self.layers[layer - 1][target].neighbors.remove(node)
+            };
+            if removed {
+                for &candidate in orphaned.iter().filter(|&&candidate| candidate != target) {
+                    self.reconnect(layer, target, candidate);
+                }
+            }
+        }
+    }
+
+    /// Removes every edge to the nodes marked in `gone` in a layer, without changing any indices.
+    ///
+    /// Each remaining node that loses an edge is offered the remaining neighbors of the gone node in its place.
+    fn repair_removed(&mut self, layer: usize, gone: &[bool]) {
+        let mut orphaned = vec![];
+        for target in (0..gone.len()).filter(|&target| !gone[target]) {
+            let neighbors = self.node_neighbors(layer, target);
+            if !neighbors.iter().any(|&n| gone[n]) {
+                continue;
+            }
+            orphaned.clear();
+            for &lost in neighbors.iter().filter(|&&n| gone[n]) {
+                orphaned.extend(
+                    self.node_neighbors(layer, lost)
+                        .iter()
+                        .filter(|&&candidate| !gone[candidate] && candidate != target),
+                );
+            }
+            if layer == 0 {
+                self.zero[target].retain(|n| !gone[n]);
+            } else {
+                self.layers[layer - 1][target]
+                    .neighbors
+                    .retain(|n| !gone[n]);
+            }
+            for &candidate in &orphaned {
+                self.reconnect(layer, target, candidate);
+            }
+        }
+    }
+
+    /// Adds `candidate` as a neighbor of `target` in a layer if `target` has an empty neighbor slot or
+    /// if `candidate` is closer to it than its current worst neighbor. With [`Params::heuristic`], a full `target`
+    /// instead selects its neighbors from its current ones and `candidate` with the same heuristic as insertion.
+    fn reconnect(&mut self, layer: usize, target: usize, candidate: usize) {
+        let zero_node = |n: usize| {
+            if layer == 0 {
+                n
+            } else {
+                self.layers[layer - 1][n].zero_node
+            }
+        };
+        let target_neighbors = if layer == 0 {
+            &self.zero[target].neighbors[..]
+        } else {
+            &self.layers[layer - 1][target].neighbors.neighbors[..]
+        };
+        if target_neighbors.contains(&candidate) {
+            return;
+        }
+
+        let empty_point = target_neighbors.partition_point(|&n| n != !0);
+        let slot = if empty_point != target_neighbors.len() {
+            empty_point
+        } else if self.params.heuristic {
+            // Select the neighbors again from the current ones and the candidate, as when inserting. The candidates
+            // are not extended, since that could bring back an edge to a node that is being removed.
+            let target_feature = &self.features[zero_node(target)];
+            let mut candidates = target_neighbors
+                .iter()
+                .chain(core::iter::once(&candidate))
+                .map(|&n| Neighbor {
+                    index: n,
+                    distance: self
+                        .metric
+                        .distance(target_feature, &self.features[zero_node(n)]),
+                })
+                .collect::<Vec<_>>();
+            candidates.sort_by_key(|c| c.distance);
+            let selected = self.select_neighbors_heuristic(
+                target_feature,
+                &candidates,
+                layer,
+                target_neighbors.len(),
+                false,
+            );
+            let target_neighbors = if layer == 0 {
+                &mut self.zero[target].neighbors[..]
+            } else {
+                &mut self.layers[layer - 1][target].neighbors.neighbors[..]
+            };
+            target_neighbors.fill(!0);
+            target_neighbors[..selected.len()].copy_from_slice(&selected);
+            return;
+        } else {
+            let target_feature = &self.features[zero_node(target)];
+            let (worst_ix, worst_distance) = target_neighbors
+                .iter()
+                .map(|&n| {
+                    self.metric
+                        .distance(target_feature, &self.features[zero_node(n)])
+                })
+                .enumerate()
+                // This was done instead of max_by_key because min_by_key takes the first equally bad element.
+                .min_by_key(|&(_, distance)| core::cmp::Reverse(distance))
+                .unwrap();
+            let distance = self
+                .metric
+                .distance(target_feature, &self.features[zero_node(candidate)]);
+            if distance < worst_distance {
+                worst_ix
+            } else {
+                return;
+            }
+        };
+
+        if layer == 0 {
+            self.zero[target].neighbors[slot] = candidate;
+        } else {
+            self.layers[layer - 1][target].neighbors.neighbors[slot] = candidate;
+        }
+    }
+}
+
+impl<Met, T, R, const M: usize, const M0: usize> Hnsw<Met, T, R, M, M0>
+where
+    R: RngCore + Clone,
+    Met: Metric<T> + Clone,
+{
+    /// Splits the HNSW into two HNSWs by a predicate `f` over the item indices.
+    ///
+    /// The items for which `f` returns `true` are reinserted into the first HNSW and the rest into the second.
+    /// Both use the metric, params, and current PRNG state of this HNSW. Items keep their relative order, so
+    /// each item's new index is the number of items before it that went into the same HNSW.
+    pub fn split<F>(self, f: F, searcher: &mut Searcher<Met::Unit>) -> (Self, Self)
+    where
+        F: Fn(usize) -> bool,
+    {
+        let mut matching =
+            Self::new_params_and_prng(self.metric.clone(), self.params, self.prng.clone());
+        let mut rest = Self::new_params_and_prng(self.metric, self.params, self.prng);
+        for (ix, feature) in self.features.into_iter().enumerate() {
+            let hnsw = if f(ix) { &mut matching } else { &mut rest };
+            let item = hnsw.insert(feature, searcher);
+            if matches!(self.removed.get(ix), Some(true)) {
+                hnsw.soft_remove(item);
+            }
+        }
+        (matching, rest)
+    }
 }
 
 impl<Met, T, R, const M: usize, const M0: usize> Default for Hnsw<Met, T, R, M, M0>
@@ -537,3 +2237,99 @@ where
         Self::new(Met::default())
     }
 }
+
+/// Finds the approximate `k` nearest neighbors in `b` of every item in `a`, searching `b` with the given `ef`.
+///
+/// Both HNSWs must store the same feature type and use the same metric, since the features of `a` are used as
+/// queries with the metric of `b`. They may have different `M`, `M0`, and PRNG types.
+///
+/// Returns the neighbors of each item of `a` in index order, each ordered from best to worst. Items of `a`
+/// removed by [`Hnsw::soft_remove`] get no neighbors, and items of `b` removed by it are never neighbors.
+pub fn ann_join<
+    Met,
+    T,
+    Ra,
+    Rb,
+    const MA: usize,
+    const M0A: usize,
+    const MB: usize,
+    const M0B: usize,
+>(
+    a: &Hnsw<Met, T, Ra, MA, M0A>,
+    b: &Hnsw<Met, T, Rb, MB, M0B>,
+    k: usize,
+    ef: usize,
+    searcher: &mut Searcher<Met::Unit>,
+) -> Vec<Vec<Neighbor<Met::Unit>>>
+where
+    Rb: RngCore,
+    Met: Metric<T>,
+{
+    a.features
+        .iter()
+        .enumerate()
+        .map(|(item, q)| {
+            if matches!(a.removed.get(item), Some(true)) {
+                vec![]
+            } else {
+                b.nearest_vec(q, k, ef, searcher)
+            }
+        })
+        .collect()
+}
+
+/// Performs the same join as [`ann_join`], but searches for the items of `a` in parallel using rayon.
+///
+/// Each rayon worker uses its own [`Searcher`].
+#[cfg(feature = "rayon")]
+pub fn par_ann_join<
+    Met,
+    T,
+    Ra,
+    Rb,
+    const MA: usize,
+    const M0A: usize,
+    const MB: usize,
+    const M0B: usize,
+>(
+    a: &Hnsw<Met, T, Ra, MA, M0A>,
+    b: &Hnsw<Met, T, Rb, MB, M0B>,
+    k: usize,
+    ef: usize,
+) -> Vec<Vec<Neighbor<Met::Unit>>>
+where
+    Rb: RngCore + Sync,
+    Met: Metric<T> + Sync,
+    Met::Unit: Send,
+    T: Sync,
+{
+    use rayon::prelude::*;
+
+    let removed = &a.removed;
+    a.features
+        .par_iter()
+        .enumerate()
+        .map_init(Searcher::default, |searcher, (item, q)| {
+            if matches!(removed.get(item), Some(true)) {
+                vec![]
+            } else {
+                b.nearest_vec(q, k, ef, searcher)
+            }
+        })
+        .collect()
+}
+
+/// Maps the index of every element that is not `gone` to its index once the gone elements are removed
+/// in order, and every gone element to `!0`.
+fn retained_indices(gone: impl Iterator<Item = bool>) -> Vec<usize> {
+    let mut next = 0;
+    gone.map(|gone| {
+        if gone {
+            !0
+        } else {
+            next += 1;
+            next - 1
+        }
+    })
+    .collect()
+}
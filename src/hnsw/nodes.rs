@@ -18,6 +18,10 @@ pub trait HasNeighbors<'a, 'b> {
     fn get_neighbors(&'b self) -> Self::NeighborIter;
 }
 
+/// Records the `M` and `M0` parameters of an HNSW so that they are checked on deserialization.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Dimensions<const M: usize, const M0: usize>;
+
 /// A node in the zero layer
 #[derive(Clone, Debug)]
 pub struct NeighborNodes<const N: usize> {
@@ -25,6 +29,52 @@ pub struct NeighborNodes<const N: usize> {
     pub neighbors: [usize; N],
 }
 
+impl<const N: usize> NeighborNodes<N> {
+    /// Removes `neighbor` if it is present, keeping the remaining neighbors contiguous.
+    ///
+    /// Returns `true` if the neighbor was removed.
+    pub fn remove(&mut self, neighbor: usize) -> bool {
+        if let Some(pos) = self.neighbors.iter().position(|&n| n == neighbor) {
+            self.neighbors.copy_within(pos + 1.., pos);
+            self.neighbors[N - 1] = !0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Removes every neighbor for which `f` returns `false`, keeping the remaining neighbors contiguous.
+    ///
+    /// Returns the number of neighbors removed.
+    pub fn retain(&mut self, mut f: impl FnMut(usize) -> bool) -> usize {
+        let mut kept = 0;
+        let mut removed = 0;
+        for ix in 0..N {
+            let n = self.neighbors[ix];
+            if n == !0 {
+                continue;
+            }
+            if f(n) {
+                self.neighbors[kept] = n;
+                kept += 1;
+            } else {
+                removed += 1;
+            }
+        }
+        self.neighbors[kept..].fill(!0);
+        removed
+    }
+
+    /// Replaces the neighbor `from` with `to` if it is present.
+    pub fn rename(&mut self, from: usize, to: usize) {
+        for n in &mut self.neighbors {
+            if *n == from {
+                *n = to;
+            }
+        }
+    }
+}
+
 impl<'a, 'b: 'a, const N: usize> HasNeighbors<'a, 'b> for NeighborNodes<N> {
     type NeighborIter = TakeWhile<Cloned<Iter<'a, usize>>, fn(&usize) -> bool>;
 
@@ -54,6 +104,7 @@ impl<'a, 'b: 'a, const N: usize> HasNeighbors<'a, 'b> for Node<N> {
 }
 
 /// The inbound nodes that are pointing to this node.
+#[allow(dead_code)]
 #[derive(Clone, Debug)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(bound = ""))]
 pub struct InboundNodes<const N: usize> {
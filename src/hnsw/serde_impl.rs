@@ -1,4 +1,5 @@
-use crate::hnsw::nodes::NeighborNodes;
+use crate::hnsw::nodes::{Dimensions, NeighborNodes};
+use crate::hnsw::ParamsMismatch;
 use core::fmt;
 use serde::{
     de::{Error, Expected, SeqAccess, Visitor},
@@ -74,3 +75,29 @@ impl<const N: usize> Expected for NeighborNodesExpectedNum<N> {
         )
     }
 }
+
+impl<const M: usize, const M0: usize> Serialize for Dimensions<M, M0> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        (M, M0).serialize(serializer)
+    }
+}
+
+impl<'de, const M: usize, const M0: usize> Deserialize<'de> for Dimensions<M, M0> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let found = <(usize, usize)>::deserialize(deserializer)?;
+        if found != (M, M0) {
+            Err(Error::custom(ParamsMismatch {
+                expected: (M, M0),
+                found,
+            }))
+        } else {
+            Ok(Dimensions)
+        }
+    }
+}
@@ -1,12 +1,13 @@
 #![no_std]
 extern crate alloc;
 
+pub mod datasets;
 mod hnsw;
 
 pub use self::hnsw::*;
 
 use ahash::RandomState;
-use alloc::{vec, vec::Vec};
+use alloc::{collections::VecDeque, vec, vec::Vec};
 use hashbrown::HashSet;
 use space::Neighbor;
 
@@ -17,6 +18,18 @@ use serde::{Deserialize, Serialize};
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Params {
     ef_construction: usize,
+    #[cfg_attr(feature = "serde", serde(default))]
+    auto_small_dataset: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    level_multiplier: Option<f64>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    max_level: Option<usize>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    heuristic: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    extend_candidates: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    keep_pruned: bool,
 }
 
 impl Params {
@@ -36,22 +49,106 @@ impl Params {
         self.ef_construction = ef_construction;
         self
     }
+
+    /// Keeps the hierarchy shallow while the HNSW is small.
+    ///
+    /// With the exponential level distribution from the paper, a small dataset can end up with several
+    /// sparse upper layers containing only a few items each. When this is enabled, an item inserted
+    /// while the HNSW contains fewer than `10000` items is not placed above level
+    /// `floor(log_M((len + 1) / M))`, which is the highest level that is expected to contain at least `M`
+    /// items (enough to fill one neighbor list). Once the HNSW contains `10000` items or more, levels are
+    /// sampled exactly as they would be otherwise.
+    ///
+    /// Defaults to `false`.
+    pub fn auto_small_dataset(mut self, auto_small_dataset: bool) -> Self {
+        self.auto_small_dataset = auto_small_dataset;
+        self
+    }
+
+    /// This is refered to as `mL` in the paper. The level of each inserted item is sampled as
+    /// `floor(-ln(uniform(0, 1)) * mL)`, so increasing this makes the hierarchy taller with more items in
+    /// each upper layer, and setting it to `0.0` puts every item in the zero layer only.
+    ///
+    /// Defaults to `1 / ln(M)`, which the paper found to be optimal.
+    pub fn level_multiplier(mut self, level_multiplier: f64) -> Self {
+        self.level_multiplier = Some(level_multiplier);
+        self
+    }
+
+    /// Caps the level of inserted items, which caps the number of layers at `max_level + 1`.
+    ///
+    /// Defaults to no cap.
+    pub fn max_level(mut self, max_level: usize) -> Self {
+        self.max_level = Some(max_level);
+        self
+    }
+
+    /// Selects the neighbors of inserted items with the heuristic from Algorithm 4 of the paper rather than
+    /// simply taking the nearest ones (Algorithm 3).
+    ///
+    /// The heuristic only keeps a candidate if it is closer to the inserted item than to any neighbor kept
+    /// so far, which spreads the neighbors out in different directions. This greatly improves recall on
+    /// clustered data, where the nearest neighbors would otherwise all be in the same cluster, at the cost of
+    /// slower insertion. It is only applied to the neighbors of the inserted item, not when an existing item
+    /// that already has a full set of neighbors gains the inserted item as a neighbor.
+    ///
+    /// Defaults to `false`.
+    pub fn heuristic(mut self, heuristic: bool) -> Self {
+        self.heuristic = heuristic;
+        self
+    }
+
+    /// When using the [`Params::heuristic`], also consider the neighbors of the candidates found by the search
+    /// as candidates. This is refered to as `extendCandidates` in the paper, which suggests it only for
+    /// extremely clustered data.
+    ///
+    /// Defaults to `false`.
+    pub fn extend_candidates(mut self, extend_candidates: bool) -> Self {
+        self.extend_candidates = extend_candidates;
+        self
+    }
+
+    /// When using the [`Params::heuristic`], fill any remaining neighbor slots with the nearest candidates
+    /// that the heuristic discarded. This is refered to as `keepPrunedConnections` in the paper.
+    ///
+    /// Defaults to `false`.
+    pub fn keep_pruned(mut self, keep_pruned: bool) -> Self {
+        self.keep_pruned = keep_pruned;
+        self
+    }
 }
 
 impl Default for Params {
     fn default() -> Self {
         Self {
             ef_construction: 400,
+            auto_small_dataset: false,
+            level_multiplier: None,
+            max_level: None,
+            heuristic: false,
+            extend_candidates: false,
+            keep_pruned: false,
         }
     }
 }
 
 /// Contains all the state used when searching the HNSW
+///
+/// Searches only borrow the HNSW immutably and keep all of their state in the searcher, so an HNSW can be shared
+/// by reference between threads for querying (it is `Sync` whenever its metric, features and PRNG are). Each
+/// thread needs its own searcher, which can be reused for every query on that thread to avoid reallocating.
 #[derive(Clone, Debug)]
 pub struct Searcher<Metric> {
     candidates: Vec<Neighbor<Metric>>,
     nearest: Vec<Neighbor<Metric>>,
     seen: HashSet<usize, RandomState>,
+    distance_computations: usize,
+    visited: usize,
+    /// The number of searches averaged by the rolling statistics, or `0` if they are disabled.
+    window: usize,
+    /// The `(distance_computations, visited)` of up to `window` searches, ending with the last one, whose entry is
+    /// only filled in when the next search starts.
+    recent: VecDeque<(usize, usize)>,
 }
 
 impl<Metric> Searcher<Metric> {
@@ -59,10 +156,77 @@ impl<Metric> Searcher<Metric> {
         Default::default()
     }
 
+    /// Creates a searcher that keeps rolling statistics over its last `window` searches, which are averaged by
+    /// [`Searcher::mean_distance_computations`] and [`Searcher::mean_visited`].
+    ///
+    /// Every search is recorded, including the searches done by [`Hnsw::insert`]. Recording pushes two counters
+    /// into a ring buffer of `window` entries at the start of each search, so the overhead is constant per search
+    /// and negligible next to the search itself. A `window` of `0` disables the statistics.
+    pub fn with_rolling_stats(window: usize) -> Self {
+        Self {
+            window,
+            recent: VecDeque::with_capacity(window),
+            ..Default::default()
+        }
+    }
+
+    /// Returns the mean of [`Searcher::distance_computations`] over the searches in the rolling window.
+    ///
+    /// Returns `None` if the searcher was not created with [`Searcher::with_rolling_stats`] or has not searched yet.
+    pub fn mean_distance_computations(&self) -> Option<f64> {
+        self.rolling_mean(self.distance_computations, |&(distances, _)| distances)
+    }
+
+    /// Returns the mean of [`Searcher::visited`] over the searches in the rolling window.
+    ///
+    /// Returns `None` if the searcher was not created with [`Searcher::with_rolling_stats`] or has not searched yet.
+    pub fn mean_visited(&self) -> Option<f64> {
+        self.rolling_mean(self.visited, |&(_, visited)| visited)
+    }
+
+    fn rolling_mean(&self, last: usize, f: impl Fn(&(usize, usize)) -> usize) -> Option<f64> {
+        if self.recent.is_empty() {
+            return None;
+        }
+        // The entry of the last search is still empty, so its live counter is used instead.
+        let total = last + self.recent.iter().rev().skip(1).map(f).sum::<usize>();
+        Some(total as f64 / self.recent.len() as f64)
+    }
+
+    /// Returns the number of distance computations performed by the last search done with this searcher.
+    ///
+    /// This counts the work of the graph search across all layers, which is a more reliable measure of the cost
+    /// of a configuration than wall-clock time. Methods that perform several searches, like
+    /// [`Hnsw::nearest_stable`], only report the last one, and distances computed outside the graph search, like
+    /// the verification done by [`Hnsw::nearest_verified`], are not counted.
+    pub fn distance_computations(&self) -> usize {
+        self.distance_computations
+    }
+
+    /// Returns the number of nodes whose neighbors were visited by the last search done with this searcher,
+    /// across all layers.
+    ///
+    /// See [`Searcher::distance_computations`] for which searches are counted.
+    pub fn visited(&self) -> usize {
+        self.visited
+    }
+
     fn clear(&mut self) {
+        // Record the previous search before its counters are reset, and make room for this one.
+        if self.window > 0 {
+            if let Some(last) = self.recent.back_mut() {
+                *last = (self.distance_computations, self.visited);
+            }
+            if self.recent.len() == self.window {
+                self.recent.pop_front();
+            }
+            self.recent.push_back((0, 0));
+        }
         self.candidates.clear();
         self.nearest.clear();
         self.seen.clear();
+        self.distance_computations = 0;
+        self.visited = 0;
     }
 }
 
@@ -72,6 +236,10 @@ impl<Metric> Default for Searcher<Metric> {
             candidates: vec![],
             nearest: vec![],
             seen: HashSet::with_hasher(RandomState::with_seeds(0, 0, 0, 0)),
+            distance_computations: 0,
+            visited: 0,
+            window: 0,
+            recent: VecDeque::new(),
         }
     }
 }
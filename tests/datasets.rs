@@ -0,0 +1,33 @@
+use hnsw::datasets::hamming_inliers;
+
+#[test]
+fn hamming_inliers_deterministic() {
+    let first = hamming_inliers(1000, 100, 0.0859, 5);
+    let second = hamming_inliers(1000, 100, 0.0859, 5);
+    assert_eq!(first, second);
+    let other = hamming_inliers(1000, 100, 0.0859, 6);
+    assert_ne!(first, other);
+
+    let (search_space, queries, ground_truth) = first;
+    assert_eq!(search_space.len(), 1000);
+    assert_eq!(queries.len(), 100);
+    assert_eq!(ground_truth.len(), 100);
+    for (&query, &distance) in queries.iter().zip(&ground_truth) {
+        let nearest = search_space
+            .iter()
+            .map(|&feature| (feature ^ query).count_ones())
+            .min()
+            .unwrap();
+        assert_eq!(distance, nearest);
+        // With about 11 bits flipped on average, every query should stay close to its source feature.
+        assert!(distance < 32);
+    }
+}
+
+#[test]
+fn hamming_inliers_exact() {
+    // Without any bit flips, every query is a feature from the search space.
+    let (search_space, queries, ground_truth) = hamming_inliers(100, 10, 0.0, 5);
+    assert!(queries.iter().all(|query| search_space.contains(query)));
+    assert!(ground_truth.iter().all(|&distance| distance == 0));
+}
@@ -7,11 +7,72 @@ use rand::seq::SliceRandom;
 use rand::Rng;
 use rand_core::SeedableRng;
 use rand_pcg::Pcg64;
-use space::Neighbor;
+use space::{Knn, Neighbor};
 
 // This can be adjusted lower if it is too slow.
 const SEARCH_SPACE_SIZE: usize = 1 << 10;
 
+type TestHnsw = Hnsw<Hamming, BitArray<16>, Pcg64, 12, 24>;
+
+/// Generates a search space of `size` random features and `queries` random queries from a fixed seed.
+fn random_features(size: usize, queries: usize) -> (Vec<BitArray<16>>, Vec<BitArray<16>>) {
+    let prng = Pcg64::from_seed([5; 32]);
+    let mut rngiter = prng.sample_iter(&Standard).map(BitArray::new);
+    let space = (&mut rngiter).take(size).collect();
+    let search = rngiter.take(queries).collect();
+    (space, search)
+}
+
+/// Builds an HNSW with the default parameters over `SEARCH_SPACE_SIZE` random features.
+///
+/// Returns the HNSW, the searcher used to build it, the search space, and `queries` random queries.
+fn random_hnsw(
+    queries: usize,
+) -> (
+    TestHnsw,
+    Searcher<u32>,
+    Vec<BitArray<16>>,
+    Vec<BitArray<16>>,
+) {
+    let mut searcher = Searcher::default();
+    let mut hnsw = TestHnsw::default();
+    let (space, search) = random_features(SEARCH_SPACE_SIZE, queries);
+    for &feature in &space {
+        hnsw.insert(feature, &mut searcher);
+    }
+    (hnsw, searcher, space, search)
+}
+
+/// Returns an output buffer for `N` neighbors to be filled by a search.
+fn neighbor_buffer<const N: usize>() -> [Neighbor<u32>; N] {
+    [Neighbor {
+        index: !0,
+        distance: !0,
+    }; N]
+}
+
+/// Finds the true 10 nearest neighbors in `features` of every query with linear search.
+fn ground_truth(features: &[BitArray<16>], queries: &[BitArray<16>]) -> Vec<Vec<usize>> {
+    queries
+        .iter()
+        .map(|query| {
+            let mut indices = (0..features.len()).collect::<Vec<_>>();
+            indices.sort_by_key(|&ix| query.distance(&features[ix]));
+            indices.truncate(10);
+            indices
+        })
+        .collect()
+}
+
+/// Builds an HNSW with `params` over `features`.
+fn build(params: Params, features: &[BitArray<16>], searcher: &mut Searcher<u32>) -> TestHnsw {
+    let mut hnsw: TestHnsw = Hnsw::new_params(Hamming, params);
+    for &feature in features {
+        hnsw.insert(feature, searcher);
+    }
+    hnsw
+}
+
 #[test]
 fn linear_1_nn() {
     let mut searcher = Searcher::default();
@@ -58,6 +119,35 @@ fn linear_1_nn() {
     assert!(pass >= 10);
 }
 
+#[test]
+fn layer_item_ids() {
+    let (hnsw, mut searcher, space, search) = random_hnsw(10);
+    let searcher = &mut searcher;
+    let mut output = neighbor_buffer::<4>();
+
+    let top = hnsw.layers() - 1;
+    assert!(top > 0);
+    for level in 1..=top {
+        for node in 0..hnsw.layer_len(level) {
+            // Every node maps to an item that is also on the level below.
+            let item = hnsw.layer_item_id(level, node);
+            assert_eq!(hnsw.layer_feature(level, node), &space[item]);
+            assert!((0..hnsw.layer_len(level - 1))
+                .any(|below| hnsw.layer_item_id(level - 1, below) == item));
+        }
+    }
+
+    // The nodes returned by a search of the top level map back to the features they were measured against.
+    for feature in &search {
+        for neighbor in hnsw.search_layer(feature, 24, top, searcher, &mut output) {
+            assert_eq!(
+                neighbor.distance,
+                feature.distance(hnsw.layer_feature(top, neighbor.index))
+            );
+        }
+    }
+}
+
 /// Does the same thing as linear_1_nn, but purposefully generates inliers in the search set.
 #[test]
 fn linear_1_nn_inliers() {
@@ -85,7 +175,7 @@ fn linear_1_nn_inliers() {
         .cloned()
         .map(|mut feature| {
             for bit in 0..128 {
-                let choice: bool = prng_bit_chooser.sample(&bernoulli);
+                let choice: bool = prng_bit_chooser.sample(bernoulli);
                 feature[bit / 8] ^= (choice as u8) << (bit % 8);
             }
             feature
@@ -122,3 +212,1146 @@ fn linear_1_nn_inliers() {
     eprintln!("pass: {}/100", pass);
     assert!(pass >= 90);
 }
+
+#[test]
+fn per_query_recall_mean() {
+    let (hnsw, mut searcher, space, search) = random_hnsw(100);
+    let searcher = &mut searcher;
+
+    let ground_truth = ground_truth(&space, &search);
+
+    let recalls = hnsw.per_query_recall(&search, &ground_truth, 10, 24, searcher);
+    assert_eq!(recalls.len(), search.len());
+    assert!(recalls.iter().all(|&recall| (0.0..=1.0).contains(&recall)));
+
+    let mean = recalls.iter().sum::<f64>() / recalls.len() as f64;
+    let recall = hnsw.recall_at_k(&search, &ground_truth, 10, 24, searcher);
+    eprintln!("recall: {}", recall);
+    assert!((mean - recall).abs() < 1e-12);
+}
+
+#[test]
+fn nearest_distances() {
+    let (hnsw, mut searcher, space, search) = random_hnsw(100);
+    let searcher = &mut searcher;
+    let mut output = neighbor_buffer::<16>();
+
+    for feature in &search {
+        let neighbors = hnsw.nearest(feature, 24, searcher, &mut output);
+        assert_eq!(neighbors.len(), 16);
+        assert!(neighbors
+            .windows(2)
+            .all(|pair| pair[0].distance <= pair[1].distance));
+        // The distances are the same ones computed by the metric.
+        for neighbor in neighbors {
+            assert_eq!(neighbor.distance, feature.distance(&space[neighbor.index]));
+        }
+    }
+}
+
+#[test]
+fn auto_small_dataset() {
+    let mut searcher = Searcher::default();
+    let searcher = &mut searcher;
+
+    let (space, search) = random_features(1000, 100);
+    let hnsw = build(Params::new(), &space, searcher);
+    let hnsw_small = build(Params::new().auto_small_dataset(true), &space, searcher);
+
+    let ground_truth = ground_truth(&space, &search);
+
+    let recall = hnsw.recall_at_k(&search, &ground_truth, 10, 24, searcher);
+    let recall_small = hnsw_small.recall_at_k(&search, &ground_truth, 10, 24, searcher);
+    eprintln!("layers: {} vs {}", hnsw.layers(), hnsw_small.layers());
+    eprintln!("recall: {} vs {}", recall, recall_small);
+    assert!(hnsw_small.layers() < hnsw.layers());
+    assert!(recall_small >= recall - 0.05);
+}
+
+#[test]
+fn nearest_within_radius() {
+    let (hnsw, mut searcher, space, search) = random_hnsw(100);
+    let searcher = &mut searcher;
+
+    let radius = 52;
+    let mut expected_total = 0;
+    let mut found_total = 0;
+    for feature in &search {
+        // Use linear search to find everything within the radius.
+        let expected = space
+            .iter()
+            .enumerate()
+            .filter(|(_, space_feature)| feature.distance(space_feature) <= radius)
+            .map(|(ix, _)| ix)
+            .collect::<Vec<_>>();
+        let found = hnsw.nearest_within(feature, radius, 24, searcher);
+        assert!(found
+            .windows(2)
+            .all(|pair| pair[0].distance <= pair[1].distance));
+        for neighbor in &found {
+            assert_eq!(feature.distance(&space[neighbor.index]), neighbor.distance);
+            assert!(expected.contains(&neighbor.index));
+        }
+        let mut indices = found.iter().map(|n| n.index).collect::<Vec<_>>();
+        indices.sort_unstable();
+        indices.dedup();
+        assert_eq!(indices.len(), found.len());
+        expected_total += expected.len();
+        found_total += found.len();
+    }
+    eprintln!("found: {}/{}", found_total, expected_total);
+    assert!(expected_total > 0);
+    assert!(found_total as f64 >= 0.9 * expected_total as f64);
+
+    // The maximum radius includes essentially everything reachable in the graph.
+    let found = hnsw.nearest_within(&search[0], 128, 24, searcher);
+    eprintln!("reachable: {}/{}", found.len(), space.len());
+    assert!(found.len() as f64 >= 0.95 * space.len() as f64);
+}
+
+#[test]
+fn split_by_parity() {
+    let (hnsw, mut searcher, space, _) = random_hnsw(0);
+    let searcher = &mut searcher;
+    let mut output = neighbor_buffer::<1>();
+
+    let (even, odd) = hnsw.split(|ix| ix % 2 == 0, searcher);
+    assert_eq!(even.len(), SEARCH_SPACE_SIZE / 2);
+    assert_eq!(odd.len(), SEARCH_SPACE_SIZE / 2);
+
+    for (ix, feature) in space.iter().enumerate() {
+        let half = if ix % 2 == 0 { &even } else { &odd };
+        assert_eq!(half.feature(ix / 2), feature);
+        let found = half.nearest(feature, 24, searcher, &mut output);
+        assert_eq!(found[0].distance, 0);
+        assert_eq!(half.feature(found[0].index), feature);
+    }
+}
+
+#[test]
+fn kth_distance_matches_knn() {
+    let (hnsw, mut searcher, _, search) = random_hnsw(100);
+    let searcher = &mut searcher;
+
+    assert_eq!(
+        TestHnsw::default().kth_distance(&BitArray::zeros(), 1, 24, searcher),
+        None
+    );
+
+    for feature in &search {
+        for k in [1, 5, 10] {
+            // `knn` uses an `ef` of `k + 16`.
+            let knn = hnsw.knn(feature, k);
+            assert_eq!(
+                hnsw.kth_distance(feature, k, k + 16, searcher),
+                Some(knn[k - 1].distance)
+            );
+        }
+        assert_eq!(hnsw.kth_distance(feature, 0, 24, searcher), None);
+        assert_eq!(
+            hnsw.kth_distance(feature, SEARCH_SPACE_SIZE + 1, 24, searcher),
+            None
+        );
+    }
+}
+
+#[test]
+fn remove() {
+    let (mut hnsw, mut searcher, mut space, _) = random_hnsw(0);
+    let searcher = &mut searcher;
+    let mut output = neighbor_buffer::<10>();
+
+    // Remove half of the items, which will also remove entry points along the way.
+    let mut prng = Pcg64::from_seed([6; 32]);
+    let mut removed = vec![];
+    for _ in 0..SEARCH_SPACE_SIZE / 2 {
+        let item = prng.gen_range(0..hnsw.len());
+        let feature = hnsw.remove(item);
+        // The index mapping mirrors `Vec::swap_remove`.
+        assert_eq!(feature, space.swap_remove(item));
+        removed.push(feature);
+    }
+    assert_eq!(hnsw.len(), space.len());
+    for (ix, feature) in space.iter().enumerate() {
+        assert_eq!(hnsw.feature(ix), feature);
+    }
+
+    // None of the removed items can be found.
+    for feature in &removed {
+        for neighbor in hnsw.nearest(feature, 24, searcher, &mut output) {
+            assert!(neighbor.index < hnsw.len());
+            assert_ne!(neighbor.distance, 0);
+        }
+    }
+
+    // The remaining items can still be found.
+    let pass = space
+        .iter()
+        .filter(|feature| hnsw.nearest(feature, 24, searcher, &mut output)[0].distance == 0)
+        .count();
+    eprintln!("pass: {}/{}", pass, space.len());
+    assert!(pass as f64 >= 0.95 * space.len() as f64);
+
+    // Remove the rest of the items and make sure the HNSW can be used again.
+    while !hnsw.is_empty() {
+        hnsw.remove(0);
+    }
+    assert_eq!(hnsw.layers(), 1);
+    hnsw.insert(space[0], searcher);
+    assert_eq!(
+        hnsw.nearest(&space[0], 24, searcher, &mut output)[0].distance,
+        0
+    );
+}
+
+#[test]
+fn remove_heuristic() {
+    let mut searcher = Searcher::default();
+    let searcher = &mut searcher;
+    let mut output = neighbor_buffer::<10>();
+
+    let (mut space, _) = random_features(SEARCH_SPACE_SIZE, 0);
+    let params = Params::new().heuristic(true).extend_candidates(true);
+    let mut hnsw = build(params, &space, searcher);
+
+    // Removing reconnects the orphaned nodes with the same heuristic used to build the graph.
+    let mut prng = Pcg64::from_seed([6; 32]);
+    for _ in 0..SEARCH_SPACE_SIZE / 2 {
+        let item = prng.gen_range(0..hnsw.len());
+        assert_eq!(hnsw.remove(item), space.swap_remove(item));
+    }
+    assert_eq!(hnsw.validate(), Ok(()));
+    for level in 0..hnsw.layers() {
+        assert!(hnsw.iter_layer_edges(level).all(|(from, to)| from != to));
+    }
+
+    let pass = space
+        .iter()
+        .filter(|feature| hnsw.nearest(feature, 24, searcher, &mut output)[0].distance == 0)
+        .count();
+    eprintln!("pass: {}/{}", pass, space.len());
+    assert!(pass as f64 >= 0.95 * space.len() as f64);
+}
+
+#[test]
+fn nearest_within_cluster() {
+    let mut searcher = Searcher::default();
+    let searcher = &mut searcher;
+    let mut hnsw: TestHnsw = Hnsw::default();
+
+    let mut prng = Pcg64::from_seed([5; 32]);
+    let centroid: BitArray<16> = BitArray::new(prng.gen());
+    let mut space = (&mut prng)
+        .sample_iter(&Standard)
+        .map(BitArray::new)
+        .take(SEARCH_SPACE_SIZE)
+        .collect::<Vec<BitArray<16>>>();
+    // Plant a cluster of distinct features within 3 bits of the centroid.
+    let mut cluster = vec![];
+    for bits in 1..=3 {
+        for _ in 0..5 {
+            let mut feature = centroid;
+            for bit in rand::seq::index::sample(&mut prng, 128, bits) {
+                feature[bit / 8] ^= 1 << (bit % 8);
+            }
+            cluster.push(space.len());
+            space.push(feature);
+        }
+    }
+
+    for &feature in &space {
+        hnsw.insert(feature, searcher);
+    }
+
+    let mut found = hnsw
+        .nearest_within(&centroid, 3, 24, searcher)
+        .into_iter()
+        .map(|n| n.index)
+        .collect::<Vec<_>>();
+    found.sort_unstable();
+    assert_eq!(found, cluster);
+
+    // Nothing is near the complement of the centroid.
+    let mut complement = centroid;
+    for byte in 0..16 {
+        complement[byte] = !complement[byte];
+    }
+    assert!(hnsw.nearest_within(&complement, 3, 24, searcher).is_empty());
+}
+
+#[test]
+fn nearest_filtered_parity() {
+    let (hnsw, mut searcher, space, search) = random_hnsw(100);
+    let searcher = &mut searcher;
+    let mut output = neighbor_buffer::<10>();
+
+    let mut pass = 0;
+    for feature in &search {
+        let found = hnsw.nearest_filtered(feature, 24, searcher, |ix| ix % 2 == 0, &mut output);
+        assert_eq!(found.len(), 10);
+        assert!(found.iter().all(|n| n.index % 2 == 0));
+        assert!(found
+            .windows(2)
+            .all(|pair| pair[0].distance <= pair[1].distance));
+        // Use linear search to find the nearest even neighbor.
+        let nearest = space
+            .iter()
+            .step_by(2)
+            .map(|space_feature| feature.distance(space_feature))
+            .min()
+            .unwrap();
+        if found[0].distance == nearest {
+            pass += 1;
+        }
+    }
+    eprintln!("pass: {}/100", pass);
+    assert!(pass >= 90);
+
+    // A filter that rejects everything finds nothing.
+    assert!(hnsw
+        .nearest_filtered(&search[0], 24, searcher, |_| false, &mut output)
+        .is_empty());
+}
+
+#[test]
+fn nearest_stable_matches_high_ef() {
+    let (hnsw, mut searcher, _, search) = random_hnsw(100);
+    let searcher = &mut searcher;
+
+    assert!(TestHnsw::default()
+        .nearest_stable(&BitArray::zeros(), 10, 1024, searcher)
+        .is_empty());
+
+    let mut pass = 0;
+    for feature in &search {
+        let stable = hnsw.nearest_stable(feature, 10, SEARCH_SPACE_SIZE, searcher);
+        assert_eq!(stable.len(), 10);
+        assert!(stable.windows(2).all(|w| w[0].distance <= w[1].distance));
+
+        // Items at the same distance may differ, so compare the distances.
+        let exact = hnsw.knn(feature, SEARCH_SPACE_SIZE);
+        if stable
+            .iter()
+            .map(|n| n.distance)
+            .eq(exact[..10].iter().map(|n| n.distance))
+        {
+            pass += 1;
+        }
+    }
+
+    eprintln!("pass: {}/100", pass);
+    assert!(pass >= 90);
+    assert!(hnsw
+        .nearest_stable(&search[0], 0, 1024, searcher)
+        .is_empty());
+}
+
+#[test]
+fn nearest_verified_never_worse() {
+    let (hnsw, mut searcher, space, search) = random_hnsw(100);
+    let searcher = &mut searcher;
+    let mut output = neighbor_buffer::<1>();
+
+    assert_eq!(
+        TestHnsw::default().nearest_verified(&BitArray::zeros(), 1, searcher),
+        None
+    );
+
+    let mut improved = 0;
+    for feature in &search {
+        for ef in [1, 4, 24] {
+            let verified = hnsw.nearest_verified(feature, ef, searcher).unwrap();
+            let nearest = hnsw.nearest(feature, ef, searcher, &mut output)[0];
+            assert_eq!(verified.distance, feature.distance(&space[verified.index]));
+            assert!(verified.distance <= nearest.distance);
+            if verified.distance < nearest.distance {
+                improved += 1;
+            }
+        }
+    }
+
+    eprintln!("improved: {}/300", improved);
+    assert!(improved > 0);
+}
+
+#[test]
+fn construction_params() {
+    let mut searcher = Searcher::default();
+    let searcher = &mut searcher;
+
+    let (space, search) = random_features(SEARCH_SPACE_SIZE, 100);
+
+    let ground_truth = ground_truth(&space, &search);
+
+    let default = build(Params::new(), &space, searcher);
+    let high = build(Params::new().ef_construction(800), &space, searcher);
+    let recall_default = default.recall_at_k(&search, &ground_truth, 10, 24, searcher);
+    let recall_high = high.recall_at_k(&search, &ground_truth, 10, 24, searcher);
+    eprintln!(
+        "recall: {} (default) vs {} (high)",
+        recall_default, recall_high
+    );
+    assert!(recall_high >= recall_default);
+
+    // The level parameters only change the shape of the hierarchy.
+    assert_eq!(
+        build(Params::new().level_multiplier(0.0), &space, searcher).layers(),
+        1
+    );
+    assert!(build(Params::new().max_level(1), &space, searcher).layers() <= 2);
+    assert!(
+        build(Params::new().level_multiplier(1.0), &space, searcher).layers() > default.layers()
+    );
+}
+
+#[test]
+fn heuristic_params() {
+    let mut searcher = Searcher::default();
+    let searcher = &mut searcher;
+
+    let (space, search) = random_features(SEARCH_SPACE_SIZE, 100);
+
+    let ground_truth = ground_truth(&space, &search);
+
+    let heuristic = Params::new().heuristic(true);
+    let variants = [
+        heuristic.ef_construction(12),
+        heuristic,
+        heuristic.keep_pruned(true),
+        heuristic.extend_candidates(true),
+        heuristic.extend_candidates(true).keep_pruned(true),
+    ];
+    let recalls = variants
+        .iter()
+        .map(|&params| {
+            build(params, &space, searcher).recall_at_k(&search, &ground_truth, 10, 24, searcher)
+        })
+        .collect::<Vec<_>>();
+    eprintln!("recalls: {:?}", recalls);
+    assert!(recalls[1] >= recalls[0]);
+    assert!(recalls.iter().all(|&recall| recall > 0.5));
+}
+
+#[test]
+fn seeded_build_is_deterministic() {
+    let (space, _) = random_features(SEARCH_SPACE_SIZE, 0);
+
+    let build = |seed: u64| {
+        let mut searcher = Searcher::default();
+        let mut hnsw: TestHnsw = Hnsw::new_prng(Hamming, Pcg64::seed_from_u64(seed));
+        for &feature in &space {
+            hnsw.insert(feature, &mut searcher);
+        }
+        hnsw
+    };
+    let graph = |hnsw: &TestHnsw| {
+        (0..hnsw.layers())
+            .map(|level| {
+                let items = (0..hnsw.layer_len(level))
+                    .map(|node| hnsw.layer_item_id(level, node))
+                    .collect::<Vec<_>>();
+                let edges = hnsw.iter_layer_edges(level).collect::<Vec<_>>();
+                (items, edges)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let a = build(42);
+    let b = build(42);
+    let c = build(43);
+    assert_eq!(graph(&a), graph(&b));
+    // The entry point is the first node on the top layer.
+    let top = a.layers() - 1;
+    assert_eq!(a.layer_item_id(top, 0), b.layer_item_id(top, 0));
+    assert_ne!(graph(&a), graph(&c));
+}
+
+#[test]
+fn insert_and_query() {
+    let mut searcher = Searcher::default();
+    let searcher = &mut searcher;
+
+    let (space, _) = random_features(SEARCH_SPACE_SIZE, 0);
+
+    for params in [Params::new(), Params::new().ef_construction(24)] {
+        let mut hnsw: TestHnsw = Hnsw::new_params(Hamming, params);
+        let mut output = neighbor_buffer::<10>();
+
+        let mut pass = 0;
+        for (ix, &feature) in space.iter().enumerate() {
+            let (item, neighbors) = hnsw.insert_and_query(feature, 10, 64, searcher);
+            assert_eq!(item, ix);
+            assert_eq!(neighbors.len(), core::cmp::min(ix, 10));
+            assert!(neighbors.iter().all(|n| n.index < item));
+            assert!(neighbors
+                .iter()
+                .all(|n| n.distance == feature.distance(&space[n.index])));
+
+            // Compare with a search after the insertion that excludes the new item.
+            let expected =
+                hnsw.nearest_filtered(&feature, 64, searcher, |index| index != item, &mut output);
+            if neighbors
+                .iter()
+                .map(|n| n.distance)
+                .eq(expected.iter().map(|n| n.distance))
+            {
+                pass += 1;
+            }
+        }
+        eprintln!("pass: {}/{}", pass, space.len());
+        assert!(pass >= space.len() * 9 / 10);
+    }
+}
+
+#[test]
+fn concurrent_queries() {
+    fn assert_sync<T: Sync>(_: &T) {}
+
+    let (hnsw, _, _, search) = random_hnsw(100);
+    assert_sync(&hnsw);
+
+    let baseline = search
+        .iter()
+        .map(|feature| hnsw.knn(feature, 10))
+        .collect::<Vec<_>>();
+
+    let hnsw = &hnsw;
+    std::thread::scope(|scope| {
+        for chunk in search.chunks(25).zip(baseline.chunks(25)) {
+            scope.spawn(move || {
+                // Each thread owns its own searcher.
+                let mut searcher = Searcher::default();
+                let mut output = neighbor_buffer::<10>();
+                for (feature, expected) in chunk.0.iter().zip(chunk.1) {
+                    let neighbors = hnsw.nearest(feature, 26, &mut searcher, &mut output);
+                    assert_eq!(neighbors, &expected[..]);
+                }
+            });
+        }
+    });
+}
+
+#[test]
+fn ann_join_brute_force() {
+    let mut searcher = Searcher::default();
+    let searcher = &mut searcher;
+    let mut a: TestHnsw = Hnsw::default();
+    let mut b: Hnsw<Hamming, BitArray<16>, Pcg64, 6, 12> = Hnsw::default();
+
+    let (space, _) = random_features(400, 0);
+    for &feature in &space[..100] {
+        a.insert(feature, searcher);
+    }
+    for &feature in &space[100..] {
+        b.insert(feature, searcher);
+    }
+
+    let join = ann_join(&a, &b, 5, 64, searcher);
+    assert_eq!(join.len(), a.len());
+
+    let mut pass = 0;
+    for ((_, feature), neighbors) in a.features().zip(&join) {
+        // Use linear search to find the true nearest neighbors in `b`.
+        let mut distances = b
+            .features()
+            .map(|(_, other)| feature.distance(other))
+            .collect::<Vec<_>>();
+        distances.sort_unstable();
+        assert_eq!(neighbors.len(), 5);
+        if neighbors
+            .iter()
+            .map(|n| n.distance)
+            .eq(distances[..5].iter().copied())
+        {
+            pass += 1;
+        }
+    }
+    eprintln!("pass: {}/100", pass);
+    assert!(pass >= 95);
+
+    #[cfg(feature = "rayon")]
+    assert_eq!(par_ann_join(&a, &b, 5, 64), join);
+}
+
+#[test]
+fn nn_distances_brute_force() {
+    let mut searcher = Searcher::default();
+    let searcher = &mut searcher;
+    let mut hnsw: TestHnsw = Hnsw::default();
+
+    assert!(hnsw.nn_distances(24, searcher).is_empty());
+
+    let (space, _) = random_features(300, 0);
+
+    hnsw.insert(space[0], searcher);
+    assert_eq!(hnsw.nn_distances(24, searcher), [None]);
+    for &feature in &space[1..] {
+        hnsw.insert(feature, searcher);
+    }
+
+    let distances = hnsw.nn_distances(24, searcher);
+    assert_eq!(distances.len(), space.len());
+    let mut pass = 0;
+    for (ix, feature) in space.iter().enumerate() {
+        // Use linear search to find the distance to the nearest other feature.
+        let nearest = space
+            .iter()
+            .enumerate()
+            .filter(|&(other, _)| other != ix)
+            .map(|(_, other)| feature.distance(other))
+            .min();
+        if distances[ix] == nearest {
+            pass += 1;
+        }
+    }
+    eprintln!("pass: {}/{}", pass, space.len());
+    assert!(pass >= space.len() * 95 / 100);
+
+    #[cfg(feature = "rayon")]
+    assert_eq!(hnsw.par_nn_distances(24), distances);
+}
+
+#[test]
+fn soft_remove_other_searches() {
+    let mut searcher = Searcher::default();
+    let searcher = &mut searcher;
+    let mut hnsw: TestHnsw = Hnsw::default();
+    let mut output = neighbor_buffer::<10>();
+
+    let (space, _) = random_features(300, 0);
+    for &feature in &space {
+        hnsw.insert(feature, searcher);
+    }
+    // Remove every third item, which includes nodes on the upper layers.
+    for item in (0..space.len()).step_by(3) {
+        hnsw.soft_remove(item);
+    }
+
+    for level in 1..hnsw.layers() {
+        for feature in &space {
+            let found = hnsw.search_layer(feature, 24, level, searcher, &mut output);
+            assert!(found
+                .iter()
+                .all(|n| !hnsw.is_removed(hnsw.layer_item_id(level, n.index))));
+        }
+    }
+
+    let distances = hnsw.nn_distances(24, searcher);
+    let mut pass = 0;
+    for (ix, feature) in space.iter().enumerate() {
+        if hnsw.is_removed(ix) {
+            assert_eq!(distances[ix], None);
+            continue;
+        }
+        // Use linear search over the remaining items to find the distance to the nearest other feature.
+        let nearest = space
+            .iter()
+            .enumerate()
+            .filter(|&(other, _)| other != ix && !hnsw.is_removed(other))
+            .map(|(_, other)| feature.distance(other))
+            .min();
+        if distances[ix] == nearest {
+            pass += 1;
+        }
+    }
+    eprintln!("pass: {}/200", pass);
+    assert!(pass >= 190);
+
+    let join = ann_join(&hnsw, &hnsw, 5, 24, searcher);
+    assert_eq!(join.len(), hnsw.len());
+    for (item, neighbors) in join.iter().enumerate() {
+        if hnsw.is_removed(item) {
+            assert!(neighbors.is_empty());
+        } else {
+            assert_eq!(neighbors.len(), 5);
+            assert!(neighbors.iter().all(|n| !hnsw.is_removed(n.index)));
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    {
+        assert_eq!(hnsw.par_nn_distances(24), distances);
+        assert_eq!(par_ann_join(&hnsw, &hnsw, 5, 24), join);
+    }
+}
+
+#[test]
+fn degree_histogram() {
+    assert_eq!(TestHnsw::default().degree_histogram(0), vec![0; 25]);
+
+    let (hnsw, _, _, _) = random_hnsw(0);
+
+    for level in 0..hnsw.layers() {
+        let histogram = hnsw.degree_histogram(level);
+        // The degree is capped at `M0` on the zero level and `M` on the others.
+        assert_eq!(histogram.len(), if level == 0 { 25 } else { 13 });
+        assert_eq!(histogram.iter().sum::<usize>(), hnsw.layer_len(level));
+        let edges = histogram
+            .iter()
+            .enumerate()
+            .map(|(degree, count)| degree * count)
+            .sum::<usize>();
+        assert_eq!(edges, hnsw.iter_layer_edges(level).count());
+    }
+    // The zero level is dense enough that most nodes have a full set of neighbors.
+    assert!(hnsw.degree_histogram(0)[24] > hnsw.len() / 2);
+    assert_eq!(hnsw.degree_histogram(hnsw.layers()), vec![0; 13]);
+}
+
+#[test]
+fn reserve_same_graph() {
+    let mut searcher = Searcher::default();
+    let searcher = &mut searcher;
+    let mut hnsw: TestHnsw = Hnsw::default();
+    let mut reserved: TestHnsw = Hnsw::with_capacity(Hamming, SEARCH_SPACE_SIZE / 2);
+
+    let (space, _) = random_features(SEARCH_SPACE_SIZE, 0);
+
+    for (ix, &feature) in space.iter().enumerate() {
+        if ix == SEARCH_SPACE_SIZE / 2 {
+            reserved.reserve(SEARCH_SPACE_SIZE / 2);
+        }
+        hnsw.insert(feature, searcher);
+        reserved.insert(feature, searcher);
+    }
+
+    assert_eq!(reserved.layers(), hnsw.layers());
+    for level in 0..hnsw.layers() {
+        assert!(reserved
+            .iter_layer_edges(level)
+            .eq(hnsw.iter_layer_edges(level)));
+    }
+}
+
+#[test]
+fn layer_sizes_decay() {
+    let empty = TestHnsw::default();
+    assert_eq!(empty.layers(), 1);
+    assert_eq!(empty.layer_len(0), 0);
+
+    let (hnsw, _, _, _) = random_hnsw(0);
+
+    let sizes = (0..hnsw.layers())
+        .map(|level| hnsw.layer_len(level))
+        .collect::<Vec<_>>();
+    eprintln!("layer sizes: {:?}", sizes);
+    assert_eq!(sizes[0], hnsw.len());
+    assert!(sizes.len() > 1);
+    assert!(sizes.windows(2).all(|w| w[0] >= w[1]));
+    assert!(sizes.iter().all(|&size| size > 0));
+    assert_eq!(hnsw.layer_len(hnsw.layers()), 0);
+}
+
+#[test]
+fn heuristic_clustered() {
+    let mut searcher = Searcher::default();
+    let searcher = &mut searcher;
+
+    // Generate tight clusters of features around random centers.
+    let mut prng = Pcg64::from_seed([5; 32]);
+    let flip = Bernoulli::new(0.05).unwrap();
+    let centers = (&mut prng)
+        .sample_iter(&Standard)
+        .map(BitArray::<16>::new)
+        .take(64)
+        .collect::<Vec<_>>();
+    let clustered = |prng: &mut Pcg64, count: usize| {
+        (0..count)
+            .map(|_| {
+                let center = centers.choose(prng).unwrap();
+                let mut bytes = **center;
+                for bit in 0..128 {
+                    if prng.sample(flip) {
+                        bytes[bit / 8] ^= 1 << (bit % 8);
+                    }
+                }
+                BitArray::new(bytes)
+            })
+            .collect::<Vec<_>>()
+    };
+    let space = clustered(&mut prng, SEARCH_SPACE_SIZE * 2);
+    let search = clustered(&mut prng, 100);
+
+    let ground_truth = ground_truth(&space, &search);
+
+    let simple = build(Params::new(), &space, searcher);
+    let heuristic = build(Params::new().heuristic(true), &space, searcher);
+    let recall_simple = simple.recall_at_k(&search, &ground_truth, 10, 24, searcher);
+    let recall_heuristic = heuristic.recall_at_k(&search, &ground_truth, 10, 24, searcher);
+    eprintln!(
+        "recall: {} (simple) vs {} (heuristic)",
+        recall_simple, recall_heuristic
+    );
+    assert!(recall_heuristic > recall_simple);
+}
+
+#[test]
+fn merge_same_as_inserting() {
+    let (hnsw, mut searcher, space, search) = random_hnsw(100);
+    let searcher = &mut searcher;
+    let mut first: TestHnsw = Hnsw::default();
+    let mut second: TestHnsw = Hnsw::new_prng(Hamming, Pcg64::from_seed([7; 32]));
+    let mut output = neighbor_buffer::<10>();
+    let mut expected = output;
+
+    let (left, right) = space.split_at(SEARCH_SPACE_SIZE / 3);
+    for &feature in left {
+        first.insert(feature, searcher);
+    }
+    for &feature in right {
+        second.insert(feature, searcher);
+    }
+
+    assert_eq!(first.merge(second, searcher), left.len());
+    assert_eq!(first.len(), space.len());
+    for (ix, feature) in first.features() {
+        assert_eq!(feature, &space[ix]);
+    }
+
+    // The first HNSW used the same PRNG as the one that all features were inserted into.
+    for level in 0..hnsw.layers() {
+        assert!(first
+            .iter_layer_edges(level)
+            .eq(hnsw.iter_layer_edges(level)));
+    }
+    for feature in &search {
+        hnsw.nearest(feature, 24, searcher, &mut expected);
+        first.nearest(feature, 24, searcher, &mut output);
+        assert_eq!(output, expected);
+    }
+}
+
+#[test]
+fn nearest_batch_matches_nearest() {
+    let (hnsw, mut searcher, _, search) = random_hnsw(100);
+    let searcher = &mut searcher;
+    let mut output = neighbor_buffer::<10>();
+
+    let batch = hnsw.nearest_batch(&search, 10, 24, searcher);
+    assert_eq!(batch.len(), search.len());
+    for (feature, neighbors) in search.iter().zip(&batch) {
+        assert_eq!(
+            neighbors,
+            &hnsw.nearest(feature, 24, searcher, &mut output)[..]
+        );
+    }
+    assert!(hnsw.nearest_batch(&[], 10, 24, searcher).is_empty());
+
+    #[cfg(feature = "rayon")]
+    assert_eq!(hnsw.par_nearest_batch(&search, 10, 24), batch);
+}
+
+#[test]
+fn neighbors_upper_layers() {
+    let (hnsw, _, _, _) = random_hnsw(0);
+    assert!(hnsw.layers() > 2);
+
+    for level in 1..hnsw.layers() {
+        let mut on_level = vec![false; hnsw.len()];
+        for node in 0..hnsw.layer_len(level) {
+            let item = hnsw.layer_item_id(level, node);
+            on_level[item] = true;
+            // The neighbors of an item are those of its node on the level.
+            let expected = hnsw
+                .iter_layer_edges(level)
+                .filter(|&(from, _)| from == node)
+                .map(|(_, to)| to)
+                .collect::<Vec<_>>();
+            assert_eq!(hnsw.neighbors(item, level), Some(&expected[..]));
+        }
+        // Items that do not reach the level have no neighbors on it.
+        for item in (0..hnsw.len()).filter(|&item| !on_level[item]) {
+            assert_eq!(hnsw.neighbors(item, level), None);
+        }
+    }
+    assert_eq!(
+        hnsw.neighbors(hnsw.layer_item_id(1, 0), hnsw.layers()),
+        None
+    );
+}
+
+#[test]
+fn clear_same_as_fresh() {
+    let (fresh, mut searcher, space, _) = random_hnsw(0);
+    let searcher = &mut searcher;
+    let (mut hnsw, _, _, _) = random_hnsw(0);
+
+    hnsw.clear();
+    assert!(hnsw.is_empty());
+    assert_eq!(hnsw.layers(), 1);
+    for &feature in &space {
+        hnsw.insert(feature, searcher);
+    }
+
+    // The PRNG is reset, so refilling draws the same levels and builds the same graph.
+    assert_eq!(hnsw.layers(), fresh.layers());
+    for level in 0..fresh.layers() {
+        assert_eq!(hnsw.layer_len(level), fresh.layer_len(level));
+        assert!((0..fresh.layer_len(level))
+            .all(|node| hnsw.layer_item_id(level, node) == fresh.layer_item_id(level, node)));
+        assert!(hnsw
+            .iter_layer_edges(level)
+            .eq(fresh.iter_layer_edges(level)));
+    }
+}
+
+#[test]
+fn memory_footprint_linear() {
+    let mut searcher = Searcher::default();
+    let searcher = &mut searcher;
+    let mut hnsw: TestHnsw = Hnsw::default();
+    assert_eq!(hnsw.memory_footprint(), 0);
+
+    let (space, _) = random_features(4 * SEARCH_SPACE_SIZE, 0);
+
+    // Each item needs at least its feature and its zero layer neighbors.
+    let per_item = core::mem::size_of::<BitArray<16>>() + 24 * core::mem::size_of::<usize>();
+    let mut footprints = vec![];
+    for chunk in space.chunks(SEARCH_SPACE_SIZE) {
+        for &feature in chunk {
+            hnsw.insert(feature, searcher);
+        }
+        let footprint = hnsw.memory_footprint();
+        assert!(footprint >= hnsw.len() * per_item);
+        // Upper layers and spare capacity add less than the items themselves.
+        assert!(footprint <= 3 * hnsw.len() * per_item);
+        footprints.push(footprint);
+    }
+    eprintln!("footprints: {:?}", footprints);
+    assert!(footprints.windows(2).all(|w| w[0] < w[1]));
+
+    let mut reserved: TestHnsw = Hnsw::with_capacity(Hamming, SEARCH_SPACE_SIZE);
+    assert!(reserved.memory_footprint() >= SEARCH_SPACE_SIZE * per_item);
+    reserved.clear();
+    assert!(reserved.memory_footprint() >= SEARCH_SPACE_SIZE * per_item);
+}
+
+#[test]
+fn tune_ef_meets_target() {
+    let (hnsw, mut searcher, space, search) = random_hnsw(100);
+    let searcher = &mut searcher;
+
+    let ground_truth = search
+        .iter()
+        .map(|q| {
+            (0..space.len())
+                .min_by_key(|&i| q.distance(&space[i]))
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+
+    let target = 0.9;
+    let ef = hnsw.tune_ef(&search, &ground_truth, target, searcher);
+    eprintln!("tuned ef: {}", ef);
+    assert!(ef <= SEARCH_SPACE_SIZE);
+
+    let mut dest = neighbor_buffer::<1>();
+    let hits = search
+        .iter()
+        .zip(&ground_truth)
+        .filter(|&(q, &truth)| {
+            hnsw.nearest(q, ef, searcher, &mut dest)[0].distance <= q.distance(&space[truth])
+        })
+        .count();
+    assert!(hits as f64 / search.len() as f64 >= target);
+
+    // Any search at all meets a target of zero.
+    assert_eq!(hnsw.tune_ef(&search, &ground_truth, 0.0, searcher), 1);
+}
+
+#[test]
+fn validate_built_graph() {
+    assert_eq!(TestHnsw::default().validate(), Ok(()));
+
+    let (mut hnsw, _, _, _) = random_hnsw(0);
+    assert_eq!(hnsw.validate(), Ok(()));
+
+    // Removal reconnects the neighbors of removed items, so the graph stays valid.
+    let mut prng = Pcg64::from_seed([6; 32]);
+    for _ in 0..SEARCH_SPACE_SIZE / 2 {
+        let item = prng.gen_range(0..hnsw.len());
+        hnsw.remove(item);
+    }
+    assert_eq!(hnsw.validate(), Ok(()));
+}
+
+#[test]
+fn nearest_deadline() {
+    let (hnsw, mut searcher, _, search) = random_hnsw(100);
+    let searcher = &mut searcher;
+    let mut output = neighbor_buffer::<10>();
+
+    assert!(TestHnsw::default()
+        .nearest_deadline(&BitArray::zeros(), 24, || true, searcher, &mut output)
+        .is_empty());
+
+    for feature in &search {
+        // An elapsed deadline still returns the entry point, which is all that was found.
+        let found = hnsw.nearest_deadline(feature, 24, || true, searcher, &mut output);
+        assert_eq!(found.len(), 1);
+        assert_eq!(
+            found[0].distance,
+            feature.distance(hnsw.feature(found[0].index))
+        );
+
+        // Stopping partway returns only real neighbors, ordered from best to worst.
+        let mut calls = 0;
+        let found = hnsw
+            .nearest_deadline(
+                feature,
+                24,
+                || {
+                    calls += 1;
+                    calls > 5
+                },
+                searcher,
+                &mut output,
+            )
+            .to_vec();
+        assert!(!found.is_empty());
+        assert!(found.windows(2).all(|w| w[0].distance <= w[1].distance));
+        for n in &found {
+            assert_eq!(n.distance, feature.distance(hnsw.feature(n.index)));
+        }
+
+        // A deadline that never passes is the same as a normal search.
+        let mut expected = output;
+        let expected = hnsw.nearest(feature, 24, searcher, &mut expected).to_vec();
+        let found = hnsw.nearest_deadline(feature, 24, || false, searcher, &mut output);
+        assert_eq!(found, &expected[..]);
+    }
+}
+
+#[test]
+fn searcher_counts_work() {
+    let (hnsw, mut searcher, _, search) = random_hnsw(100);
+    let searcher = &mut searcher;
+    let mut output = neighbor_buffer::<10>();
+
+    let mut work = |ef| {
+        let mut distances = 0;
+        let mut visited = 0;
+        for feature in &search {
+            hnsw.nearest(feature, ef, searcher, &mut output);
+            assert!(searcher.distance_computations() > 0);
+            assert!(searcher.visited() > 0);
+            distances += searcher.distance_computations();
+            visited += searcher.visited();
+        }
+        (distances, visited)
+    };
+    let (low_distances, low_visited) = work(10);
+    let (high_distances, high_visited) = work(100);
+    eprintln!(
+        "ef 10: {} distances, {} visited",
+        low_distances, low_visited
+    );
+    eprintln!(
+        "ef 100: {} distances, {} visited",
+        high_distances, high_visited
+    );
+    assert!(high_distances > low_distances);
+    assert!(high_visited > low_visited);
+}
+
+#[test]
+fn searcher_rolling_stats() {
+    let (hnsw, mut searcher, _, search) = random_hnsw(20);
+    let searcher = &mut searcher;
+    let mut output = neighbor_buffer::<10>();
+    assert_eq!(searcher.mean_distance_computations(), None);
+
+    let mut rolling = Searcher::with_rolling_stats(5);
+    assert_eq!(rolling.mean_visited(), None);
+    let mut work = vec![];
+    for (i, feature) in search.iter().enumerate() {
+        // Vary the work done by each query.
+        let ef = 10 + 10 * (i % 4);
+        hnsw.nearest(feature, ef, &mut rolling, &mut output);
+        work.push((rolling.distance_computations(), rolling.visited()));
+        let window = &work[work.len().saturating_sub(5)..];
+        let mean = |f: fn(&(usize, usize)) -> usize| {
+            window.iter().map(f).sum::<usize>() as f64 / window.len() as f64
+        };
+        assert_eq!(rolling.mean_distance_computations(), Some(mean(|w| w.0)));
+        assert_eq!(rolling.mean_visited(), Some(mean(|w| w.1)));
+    }
+}
+
+#[test]
+fn searcher_rolling_stats_window() {
+    let (hnsw, _, _, search) = random_hnsw(4);
+    let mut output = neighbor_buffer::<10>();
+
+    let mut rolling = Searcher::with_rolling_stats(3);
+    let mut work = vec![];
+    for (i, feature) in search.iter().enumerate() {
+        hnsw.nearest(feature, 10 + 10 * i, &mut rolling, &mut output);
+        work.push(rolling.distance_computations());
+    }
+    // The window holds exactly the last 3 of the 4 searches.
+    let mean = work[1..].iter().sum::<usize>() as f64 / 3.0;
+    assert_eq!(rolling.mean_distance_computations(), Some(mean));
+
+    // A window of 1 only holds the last search.
+    let mut last = Searcher::with_rolling_stats(1);
+    for feature in &search {
+        hnsw.nearest(feature, 24, &mut last, &mut output);
+        assert_eq!(
+            last.mean_distance_computations(),
+            Some(last.distance_computations() as f64)
+        );
+    }
+}
+
+#[test]
+fn soft_remove_and_compact() {
+    let (mut hnsw, mut searcher, mut space, _) = random_hnsw(0);
+    let searcher = &mut searcher;
+    let mut output = neighbor_buffer::<10>();
+
+    // Soft remove half of the items.
+    let mut removed = (0..SEARCH_SPACE_SIZE).collect::<Vec<_>>();
+    removed.shuffle(&mut Pcg64::from_seed([6; 32]));
+    removed.truncate(SEARCH_SPACE_SIZE / 2);
+    for &item in &removed {
+        hnsw.soft_remove(item);
+    }
+    assert_eq!(hnsw.len(), SEARCH_SPACE_SIZE);
+    assert!(removed.iter().all(|&item| hnsw.is_removed(item)));
+
+    // Soft removed items are never returned, but the rest can still be found.
+    let mut pass = 0;
+    for (item, feature) in space.iter().enumerate() {
+        let found = hnsw.nearest(feature, 24, searcher, &mut output);
+        assert!(!found.is_empty());
+        assert!(found.iter().all(|n| !hnsw.is_removed(n.index)));
+        if !hnsw.is_removed(item) && found[0].distance == 0 {
+            pass += 1;
+        }
+    }
+    eprintln!("pass before compaction: {}/{}", pass, SEARCH_SPACE_SIZE / 2);
+    assert!(pass as f64 >= 0.95 * (SEARCH_SPACE_SIZE / 2) as f64);
+
+    // Compaction keeps the remaining items in order, like `Vec::retain`.
+    assert_eq!(hnsw.compact(), SEARCH_SPACE_SIZE / 2);
+    assert_eq!(hnsw.compact(), 0);
+    let mut ix = 0;
+    space.retain(|_| {
+        ix += 1;
+        !removed.contains(&(ix - 1))
+    });
+    assert_eq!(hnsw.len(), space.len());
+    for (ix, feature) in space.iter().enumerate() {
+        assert_eq!(hnsw.feature(ix), feature);
+        assert!(!hnsw.is_removed(ix));
+    }
+    assert_eq!(hnsw.validate(), Ok(()));
+
+    let pass = space
+        .iter()
+        .filter(|feature| hnsw.nearest(feature, 24, searcher, &mut output)[0].distance == 0)
+        .count();
+    eprintln!("pass after compaction: {}/{}", pass, space.len());
+    assert!(pass as f64 >= 0.95 * space.len() as f64);
+}
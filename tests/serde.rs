@@ -1,7 +1,11 @@
 #![cfg(feature = "serde")]
 
-use hnsw::{Hnsw, Searcher};
+use hnsw::{GraphError, Hnsw, ParamsMismatch, Searcher};
+use rand::distributions::Standard;
+use rand::Rng;
+use rand_core::SeedableRng;
 use rand_pcg::Pcg64;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use space::{Metric, Neighbor};
 
@@ -16,6 +20,14 @@ impl Metric<u8> for Hamming {
     }
 }
 
+impl Metric<u128> for Hamming {
+    type Unit = u32;
+
+    fn distance(&self, &a: &u128, &b: &u128) -> u32 {
+        (a ^ b).count_ones()
+    }
+}
+
 fn test_hnsw_discrete() -> (Hnsw<Hamming, u8, Pcg64, 12, 24>, Searcher<u8>) {
     let mut searcher = Searcher::default();
     let mut hnsw = Hnsw::new(Hamming);
@@ -87,3 +99,336 @@ fn serde() {
         ]
     );
 }
+
+fn test_hnsw_random() -> (Hnsw<Hamming, u128, Pcg64, 12, 24>, Searcher<u32>) {
+    let mut searcher = Searcher::default();
+    let mut hnsw = Hnsw::new(Hamming);
+
+    let prng = Pcg64::from_seed([5; 32]);
+    for feature in prng.sample_iter(&Standard).take(1 << 10) {
+        hnsw.insert(feature, &mut searcher);
+    }
+
+    (hnsw, searcher)
+}
+
+#[test]
+fn serde_random_queries() {
+    let (hnsw_unser, mut searcher) = test_hnsw_random();
+    let hnsw_str = serde_json::to_string(&hnsw_unser).expect("failed to serialize hnsw");
+    let hnsw: Hnsw<Hamming, u128, Pcg64, 12, 24> =
+        serde_json::from_str(&hnsw_str).expect("failed to deserialize hnsw");
+
+    let prng = Pcg64::from_seed([6; 32]);
+    for query in prng.sample_iter::<u128, _>(&Standard).take(1000) {
+        for ef in [1, 10, 24, 100] {
+            let mut expected = [Neighbor {
+                index: !0,
+                distance: !0,
+            }; 10];
+            let mut neighbors = expected;
+            hnsw_unser.nearest(&query, ef, &mut searcher, &mut expected);
+            hnsw.nearest(&query, ef, &mut searcher, &mut neighbors);
+            assert_eq!(neighbors, expected);
+        }
+    }
+}
+
+#[test]
+fn serde_mismatched_m() {
+    let (hnsw, _) = test_hnsw_random();
+    let hnsw_str = serde_json::to_string(&hnsw).expect("failed to serialize hnsw");
+    let error = serde_json::from_str::<Hnsw<Hamming, u128, Pcg64, 16, 24>>(&hnsw_str)
+        .err()
+        .expect("deserialized hnsw with mismatched M");
+    let mismatch = ParamsMismatch {
+        expected: (16, 24),
+        found: (12, 24),
+    };
+    assert!(error.to_string().starts_with(&mismatch.to_string()));
+}
+
+#[test]
+fn serde_mismatched_m_zero_layer_only() {
+    let (hnsw, _) = test_hnsw_discrete();
+    assert_eq!(hnsw.layers(), 1);
+    let hnsw_str = serde_json::to_string(&hnsw).expect("failed to serialize hnsw");
+    let error = serde_json::from_str::<Hnsw<Hamming, u8, Pcg64, 16, 24>>(&hnsw_str)
+        .err()
+        .expect("deserialized hnsw with mismatched M");
+    let mismatch = ParamsMismatch {
+        expected: (16, 24),
+        found: (12, 24),
+    };
+    assert!(error.to_string().starts_with(&mismatch.to_string()));
+}
+
+#[test]
+fn serde_mismatched_m_empty() {
+    let hnsw: Hnsw<Hamming, u8, Pcg64, 12, 24> = Hnsw::new(Hamming);
+    let hnsw_str = serde_json::to_string(&hnsw).expect("failed to serialize hnsw");
+    let error = serde_json::from_str::<Hnsw<Hamming, u8, Pcg64, 16, 24>>(&hnsw_str)
+        .err()
+        .expect("deserialized hnsw with mismatched M");
+    let mismatch = ParamsMismatch {
+        expected: (16, 24),
+        found: (12, 24),
+    };
+    assert!(error.to_string().starts_with(&mismatch.to_string()));
+    let error = serde_json::from_str::<Hnsw<Hamming, u8, Pcg64, 12, 32>>(&hnsw_str)
+        .err()
+        .expect("deserialized hnsw with mismatched M0");
+    let mismatch = ParamsMismatch {
+        expected: (12, 32),
+        found: (12, 24),
+    };
+    assert!(error.to_string().starts_with(&mismatch.to_string()));
+}
+
+#[test]
+fn serde_prune_invalid_edges() {
+    let (hnsw_unser, mut searcher) = test_hnsw_discrete();
+    let hnsw_str = serde_json::to_string(&hnsw_unser).expect("failed to serialize hnsw");
+
+    // Every item is connected to the other 7 items, so inject an edge to a missing item into the first free slot
+    // of the first item in the zero layer.
+    let hnsw_str = edit_zero_layer(&hnsw_str, |zero| zero[0][7] = 1000);
+
+    let mut hnsw: Hnsw<Hamming, u8, Pcg64, 12, 24> =
+        serde_json::from_str(&hnsw_str).expect("failed to deserialize hnsw");
+    assert_eq!(hnsw.iter_layer_edges(0).next(), Some((0, 1)));
+    assert_eq!(hnsw.iter_layer_edges(0).nth(7), Some((0, 1000)));
+    assert_eq!(hnsw.prune_invalid_edges(), 1);
+    assert_eq!(hnsw.prune_invalid_edges(), 0);
+    for level in 0..hnsw.layers() {
+        assert!(hnsw
+            .iter_layer_edges(level)
+            .eq(hnsw_unser.iter_layer_edges(level)));
+    }
+
+    for query in 0..16 {
+        let mut expected = [Neighbor {
+            index: !0,
+            distance: !0,
+        }; 8];
+        let mut neighbors = expected;
+        hnsw_unser.nearest(&query, 24, &mut searcher, &mut expected);
+        hnsw.nearest(&query, 24, &mut searcher, &mut neighbors);
+        assert_eq!(neighbors, expected);
+    }
+}
+
+#[test]
+fn serde_prune_missing_features() {
+    let (hnsw_unser, mut searcher) = test_hnsw_discrete();
+    let hnsw_str = serde_json::to_string(&hnsw_unser).expect("failed to serialize hnsw");
+
+    // Drop the feature of the last item, which every other item is connected to.
+    let hnsw_str = edit_field(&hnsw_str, "features", |features: &mut Vec<u8>| {
+        features.pop();
+    });
+
+    let mut hnsw: Hnsw<Hamming, u8, Pcg64, 12, 24> =
+        serde_json::from_str(&hnsw_str).expect("failed to deserialize hnsw");
+    assert_eq!(hnsw.prune_invalid_edges(), 7);
+    assert_eq!(hnsw.prune_invalid_edges(), 0);
+    assert!(hnsw.iter_layer_edges(0).all(|(_, to)| to != 7));
+
+    for query in 0..16 {
+        let mut neighbors = [Neighbor {
+            index: !0,
+            distance: !0,
+        }; 8];
+        let found = hnsw.nearest(&query, 24, &mut searcher, &mut neighbors);
+        assert_eq!(found.len(), 7);
+        assert!(found.iter().all(|n| n.index != 7));
+    }
+}
+
+#[test]
+fn serde_prune_invalid_upper_nodes() {
+    let (hnsw_unser, mut searcher) = test_hnsw_random();
+    assert!(hnsw_unser.layers() > 2);
+    let hnsw_str = serde_json::to_string(&hnsw_unser).expect("failed to serialize hnsw");
+
+    // Point the last node on the first upper layer at a missing item, and another node at a missing node below.
+    let last = hnsw_unser.layer_len(1) - 1;
+    let hnsw_str = edit_field(
+        &hnsw_str,
+        "layers",
+        |layers: &mut Vec<Vec<serde_json::Value>>| {
+            layers[0][last]["zero_node"] = 5000.into();
+            layers[0][1]["next_node"] = 5000.into();
+        },
+    );
+
+    let mut hnsw: Hnsw<Hamming, u128, Pcg64, 12, 24> =
+        serde_json::from_str(&hnsw_str).expect("failed to deserialize hnsw");
+    let dropped_edges = hnsw_unser
+        .iter_layer_edges(1)
+        .filter(|&(from, to)| from == last || to == last)
+        .count();
+    assert_eq!(hnsw.prune_invalid_edges(), dropped_edges);
+    assert_eq!(hnsw.prune_invalid_edges(), 0);
+    assert_eq!(hnsw.layer_len(1), last);
+    assert_eq!(hnsw.validate(), Ok(()));
+    assert!(hnsw.iter_layer_edges(0).eq(hnsw_unser.iter_layer_edges(0)));
+    for level in 2..hnsw.layers() {
+        assert_eq!(hnsw.layer_len(level), hnsw_unser.layer_len(level));
+    }
+
+    let prng = Pcg64::from_seed([6; 32]);
+    for query in prng.sample_iter::<u128, _>(&Standard).take(100) {
+        let mut neighbors = [Neighbor {
+            index: !0,
+            distance: !0,
+        }; 10];
+        assert_eq!(
+            hnsw.nearest(&query, 24, &mut searcher, &mut neighbors)
+                .len(),
+            10
+        );
+    }
+}
+
+/// Applies `f` to the zero layer of a serialized HNSW, where each node is a list of neighbors padded with
+/// `usize::MAX`.
+fn edit_zero_layer(hnsw_str: &str, f: impl FnOnce(&mut Vec<Vec<usize>>)) -> String {
+    edit_field(hnsw_str, "zero", f)
+}
+
+/// Applies `f` to a field of a serialized HNSW, leaving the rest of the string untouched.
+fn edit_field<V: Serialize + DeserializeOwned>(
+    hnsw_str: &str,
+    field: &str,
+    f: impl FnOnce(&mut V),
+) -> String {
+    let key = format!("\"{}\":", field);
+    let start = hnsw_str.find(&key).unwrap() + key.len();
+    let mut values = serde_json::Deserializer::from_str(&hnsw_str[start..]).into_iter::<V>();
+    let mut value = values.next().unwrap().unwrap();
+    let end = start + values.byte_offset();
+    f(&mut value);
+    format!(
+        "{}{}{}",
+        &hnsw_str[..start],
+        serde_json::to_string(&value).unwrap(),
+        &hnsw_str[end..]
+    )
+}
+
+#[test]
+fn serde_validate() {
+    let (hnsw, _) = test_hnsw_discrete();
+    assert_eq!(hnsw.validate(), Ok(()));
+    let hnsw_str = serde_json::to_string(&hnsw).expect("failed to serialize hnsw");
+
+    // Point the first item at a missing item.
+    let dangling = edit_zero_layer(&hnsw_str, |zero| zero[0][7] = 1000);
+    let hnsw: Hnsw<Hamming, u8, Pcg64, 12, 24> =
+        serde_json::from_str(&dangling).expect("failed to deserialize hnsw");
+    assert_eq!(
+        hnsw.validate(),
+        Err(GraphError::DanglingNeighbor {
+            level: 0,
+            node: 0,
+            neighbor: 1000
+        })
+    );
+
+    // Remove every edge into the last item, keeping the neighbor lists contiguous.
+    let orphaned = edit_zero_layer(&hnsw_str, |zero| {
+        for neighbors in zero {
+            neighbors.retain(|&n| n != 7);
+            neighbors.resize(24, usize::MAX);
+        }
+    });
+    let hnsw: Hnsw<Hamming, u8, Pcg64, 12, 24> =
+        serde_json::from_str(&orphaned).expect("failed to deserialize hnsw");
+    assert_eq!(hnsw.validate(), Err(GraphError::Unreachable { item: 7 }));
+}
+
+#[test]
+fn serde_validate_features() {
+    let (hnsw, _) = test_hnsw_discrete();
+    let hnsw_str = serde_json::to_string(&hnsw).expect("failed to serialize hnsw");
+
+    let missing = edit_field(&hnsw_str, "features", |features: &mut Vec<u8>| {
+        features.pop();
+    });
+    let hnsw: Hnsw<Hamming, u8, Pcg64, 12, 24> =
+        serde_json::from_str(&missing).expect("failed to deserialize hnsw");
+    assert_eq!(
+        hnsw.validate(),
+        Err(GraphError::FeatureCount {
+            items: 8,
+            features: 7
+        })
+    );
+}
+
+#[test]
+fn serde_validate_upper_layers() {
+    let (hnsw, _) = test_hnsw_random();
+    assert!(hnsw.layers() > 2);
+    assert_eq!(hnsw.validate(), Ok(()));
+    let hnsw_str = serde_json::to_string(&hnsw).expect("failed to serialize hnsw");
+
+    // Point a node on the second level at a missing item.
+    let zero_node = edit_field(
+        &hnsw_str,
+        "layers",
+        |layers: &mut Vec<Vec<serde_json::Value>>| {
+            layers[1][1]["zero_node"] = 5000.into();
+        },
+    );
+    let hnsw: Hnsw<Hamming, u128, Pcg64, 12, 24> =
+        serde_json::from_str(&zero_node).expect("failed to deserialize hnsw");
+    assert_eq!(
+        hnsw.validate(),
+        Err(GraphError::DanglingZeroNode {
+            level: 2,
+            node: 1,
+            zero_node: 5000
+        })
+    );
+
+    // Point a node on the first level at a missing node below.
+    let missing = edit_field(
+        &hnsw_str,
+        "layers",
+        |layers: &mut Vec<Vec<serde_json::Value>>| {
+            layers[0][1]["next_node"] = 5000.into();
+        },
+    );
+    let hnsw: Hnsw<Hamming, u128, Pcg64, 12, 24> =
+        serde_json::from_str(&missing).expect("failed to deserialize hnsw");
+    assert_eq!(
+        hnsw.validate(),
+        Err(GraphError::DanglingNextNode {
+            level: 1,
+            node: 1,
+            next_node: 5000
+        })
+    );
+
+    // Point a node on the second level at the node of a different item below.
+    let mismatched = edit_field(
+        &hnsw_str,
+        "layers",
+        |layers: &mut Vec<Vec<serde_json::Value>>| {
+            let next_node = layers[1][1]["next_node"].as_u64().unwrap();
+            layers[1][1]["next_node"] = (next_node + 1).into();
+        },
+    );
+    let hnsw: Hnsw<Hamming, u128, Pcg64, 12, 24> =
+        serde_json::from_str(&mismatched).expect("failed to deserialize hnsw");
+    assert!(matches!(
+        hnsw.validate(),
+        Err(GraphError::DanglingNextNode {
+            level: 2,
+            node: 1,
+            ..
+        })
+    ));
+}
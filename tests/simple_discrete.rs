@@ -1,6 +1,7 @@
 //! Useful tests for debugging since they are hand-written and easy to see the debugging output.
 
-use hnsw::{Hnsw, Searcher};
+use hnsw::{Hnsw, Searcher, Stats};
+use rand_core::SeedableRng;
 use rand_pcg::Pcg64;
 use space::{Metric, Neighbor};
 
@@ -34,6 +35,17 @@ fn insertion_discrete() {
     test_hnsw_discrete();
 }
 
+#[test]
+fn len_discrete() {
+    let hnsw: Hnsw<Hamming, u8, Pcg64, 12, 24> = Hnsw::new(Hamming);
+    assert!(hnsw.is_empty());
+    assert_eq!(hnsw.len(), 0);
+
+    let (hnsw, _) = test_hnsw_discrete();
+    assert!(!hnsw.is_empty());
+    assert_eq!(hnsw.len(), 8);
+}
+
 #[test]
 fn nearest_neighbor_discrete() {
     let (hnsw, mut searcher) = test_hnsw_discrete();
@@ -87,3 +99,356 @@ fn nearest_neighbor_discrete() {
         ]
     );
 }
+
+#[test]
+fn exhaustive_search_seeds_discrete() {
+    let features = [
+        0b0001, 0b0010, 0b0100, 0b1000, 0b0011, 0b0110, 0b1100, 0b1001,
+    ];
+
+    // With only 8 items and `ef` larger than that, every item must be found from every query, whatever levels
+    // the items were inserted at.
+    for seed in 0..64 {
+        let mut searcher = Searcher::default();
+        let mut hnsw: Hnsw<Hamming, u8, Pcg64, 12, 24> =
+            Hnsw::new_prng(Hamming, Pcg64::from_seed([seed; 32]));
+        for &feature in &features {
+            hnsw.insert(feature, &mut searcher);
+        }
+
+        for query in 0..16 {
+            let mut neighbors = [Neighbor {
+                index: !0,
+                distance: !0,
+            }; 8];
+            let found = hnsw.nearest(&query, 24, &mut searcher, &mut neighbors);
+            let mut items = found.iter().map(|n| n.index).collect::<Vec<_>>();
+            items.sort_unstable();
+            assert_eq!(items, [0, 1, 2, 3, 4, 5, 6, 7], "seed {}", seed);
+        }
+    }
+}
+
+#[test]
+fn nearest_dest_longer_than_results_discrete() {
+    let (hnsw, mut searcher) = test_hnsw_discrete();
+    let mut neighbors = [Neighbor {
+        index: !0,
+        distance: !0,
+    }; 16];
+
+    // There are only 8 items, so only the first 8 slots are filled and returned.
+    let found = hnsw.nearest(&0b0001, 24, &mut searcher, &mut neighbors);
+    assert_eq!(found.len(), 8);
+    assert_eq!(
+        found[0],
+        Neighbor {
+            index: 0,
+            distance: 0
+        }
+    );
+    assert!(neighbors[8..].iter().all(|n| n.index == !0));
+
+    // An `ef` smaller than the slice also limits the neighbors returned.
+    assert_eq!(
+        hnsw.nearest(&0b0001, 4, &mut searcher, &mut neighbors)
+            .len(),
+        4
+    );
+}
+
+#[test]
+fn clear_discrete() {
+    let mut searcher = Searcher::default();
+    // Every byte in a scrambled order, which is far more items than `ef`, so searches are not exhaustive.
+    let features = (0..=255u8).map(|x| x.wrapping_mul(37)).collect::<Vec<_>>();
+    let mut fresh: Hnsw<Hamming, u8, Pcg64, 12, 24> = Hnsw::new(Hamming);
+    let mut hnsw: Hnsw<Hamming, u8, Pcg64, 12, 24> = Hnsw::new(Hamming);
+    for &feature in &features {
+        fresh.insert(feature, &mut searcher);
+        hnsw.insert(feature, &mut searcher);
+    }
+    assert!(fresh.layers() > 1);
+
+    hnsw.clear();
+    assert!(hnsw.is_empty());
+    assert_eq!(hnsw.len(), 0);
+    assert_eq!(hnsw.layers(), 1);
+
+    for (ix, &feature) in features.iter().enumerate() {
+        assert_eq!(hnsw.insert(feature, &mut searcher), ix);
+    }
+
+    // The refilled graph is identical to the fresh one, on every layer.
+    assert_eq!(hnsw.layers(), fresh.layers());
+    for level in 0..fresh.layers() {
+        assert_eq!(hnsw.layer_len(level), fresh.layer_len(level));
+        for node in 0..fresh.layer_len(level) {
+            assert_eq!(
+                hnsw.layer_item_id(level, node),
+                fresh.layer_item_id(level, node)
+            );
+        }
+        assert!(hnsw
+            .iter_layer_edges(level)
+            .eq(fresh.iter_layer_edges(level)));
+    }
+
+    for query in 0..=255 {
+        let mut expected = [Neighbor {
+            index: !0,
+            distance: !0,
+        }; 8];
+        let mut found = expected;
+        fresh.nearest(&query, 8, &mut searcher, &mut expected);
+        hnsw.nearest(&query, 8, &mut searcher, &mut found);
+        assert_eq!(found, expected);
+    }
+}
+
+#[test]
+fn layer_edges_discrete() {
+    let (hnsw, _) = test_hnsw_discrete();
+
+    // With so few items, every item is connected to every other item in the zero layer.
+    let mut edges = hnsw.iter_layer_edges(0).collect::<Vec<_>>();
+    edges.sort_unstable();
+    let complete = (0..8)
+        .flat_map(|from| {
+            (0..8)
+                .filter(move |&to| to != from)
+                .map(move |to| (from, to))
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(edges, complete);
+
+    for level in 1..hnsw.layers() {
+        assert!(hnsw
+            .iter_layer_edges(level)
+            .all(|(from, to)| from < hnsw.layer_len(level) && to < hnsw.layer_len(level)));
+    }
+    assert_eq!(hnsw.iter_layer_edges(hnsw.layers()).count(), 0);
+}
+
+#[test]
+fn nearest_dedup_discrete() {
+    let (mut hnsw, mut searcher) = test_hnsw_discrete();
+    // Insert duplicates of `0b0001` and `0b0011` at indices 8 and 9.
+    assert_eq!(hnsw.insert(0b0001, &mut searcher), 8);
+    assert_eq!(hnsw.insert(0b0011, &mut searcher), 9);
+
+    let mut neighbors = [Neighbor {
+        index: !0,
+        distance: !0,
+    }; 10];
+
+    let plain = hnsw.nearest(&0b0001, 24, &mut searcher, &mut neighbors);
+    assert_eq!(plain.len(), 10);
+
+    let deduped = hnsw.nearest_dedup(&0b0001, 24, &mut searcher, &mut neighbors);
+    assert_eq!(deduped.len(), 8);
+    let mut indices = deduped.iter().map(|n| n.index).collect::<Vec<_>>();
+    indices.sort_unstable();
+    assert_eq!(indices, (0..8).collect::<Vec<_>>());
+    assert_eq!(
+        deduped[0],
+        Neighbor {
+            index: 0,
+            distance: 0
+        }
+    );
+}
+
+#[test]
+fn features_discrete() {
+    let (hnsw, _) = test_hnsw_discrete();
+    let features = [
+        0b0001, 0b0010, 0b0100, 0b1000, 0b0011, 0b0110, 0b1100, 0b1001,
+    ];
+
+    assert_eq!(
+        hnsw.features()
+            .map(|(item, &feature)| (item, feature))
+            .collect::<Vec<_>>(),
+        features.iter().copied().enumerate().collect::<Vec<_>>()
+    );
+    for (item, feature) in features.iter().enumerate() {
+        assert_eq!(hnsw.get(item), Some(feature));
+    }
+    assert_eq!(hnsw.get(features.len()), None);
+}
+
+#[test]
+fn nearest_ties_discrete() {
+    let (mut hnsw, mut searcher) = test_hnsw_discrete();
+    let mut neighbors = [Neighbor {
+        index: !0,
+        distance: !0,
+    }; 10];
+
+    // Every item is at a distance of 1 or 2 from `0b0000`, and the items at each distance are ordered by index.
+    let expected = [0, 1, 2, 3, 4, 5, 6, 7]
+        .iter()
+        .map(|&index| Neighbor {
+            index,
+            distance: if index < 4 { 1 } else { 2 },
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        hnsw.nearest(&0b0000, 24, &mut searcher, &mut neighbors),
+        &expected[..]
+    );
+
+    // Duplicates are ordered by index too.
+    hnsw.insert(0b0001, &mut searcher);
+    hnsw.insert(0b0100, &mut searcher);
+    let found = hnsw.nearest(&0b0000, 24, &mut searcher, &mut neighbors);
+    assert_eq!(
+        found.iter().map(|n| n.index).collect::<Vec<_>>(),
+        [0, 1, 2, 3, 8, 9, 4, 5, 6, 7]
+    );
+}
+
+#[test]
+fn nearest_k_discrete() {
+    let (hnsw, mut searcher) = test_hnsw_discrete();
+
+    // Asking for more items than there are returns all of them from best to worst.
+    assert_eq!(
+        hnsw.nearest_k(&0b0001, 20, 24, &mut searcher),
+        [0, 4, 7, 1, 2, 3, 5, 6]
+    );
+    assert_eq!(hnsw.nearest_k(&0b0001, 3, 24, &mut searcher), [0, 4, 7]);
+    assert!(hnsw.nearest_k(&0b0001, 0, 24, &mut searcher).is_empty());
+}
+
+#[test]
+fn write_dot_discrete() {
+    let (hnsw, _) = test_hnsw_discrete();
+
+    let mut dot = String::new();
+    hnsw.write_dot(0, &mut dot).unwrap();
+    assert!(dot.starts_with("digraph level0 {\n"));
+    assert!(dot.ends_with("}\n"));
+    assert!(dot.contains("    4 [label=\"4\"];\n"));
+    assert!(dot.contains("    0 -> 4;\n"));
+    assert_eq!(dot.matches("[label=").count(), 8);
+    // Every item is connected to every other item.
+    assert_eq!(dot.matches(" -> ").count(), 8 * 7);
+
+    for level in 1..hnsw.layers() {
+        let mut dot = String::new();
+        hnsw.write_dot(level, &mut dot).unwrap();
+        assert_eq!(dot.matches("[label=").count(), hnsw.layer_len(level));
+        assert_eq!(
+            dot.matches(" -> ").count(),
+            hnsw.iter_layer_edges(level).count()
+        );
+    }
+}
+
+#[test]
+fn insert_id_discrete() {
+    let mut searcher = Searcher::default();
+    let mut hnsw: Hnsw<Hamming, u8, Pcg64, 12, 24> = Hnsw::new(Hamming);
+    let features = [0b0001, 0b0010, 0b0100, 0b1000, 0b0011, 0b0110];
+
+    let ids = features
+        .iter()
+        .map(|&feature| hnsw.insert(feature, &mut searcher))
+        .collect::<Vec<_>>();
+    assert_eq!(ids, [0, 1, 2, 3, 4, 5]);
+
+    // Searching for each feature finds it under the returned id.
+    for (&feature, &id) in features.iter().zip(&ids) {
+        assert_eq!(hnsw.nearest_k(&feature, 1, 24, &mut searcher), [id]);
+        assert_eq!(hnsw.feature(id), &feature);
+    }
+}
+
+#[test]
+fn stats_discrete() {
+    let hnsw: Hnsw<Hamming, u8, Pcg64, 12, 24> = Hnsw::new(Hamming);
+    let stats = hnsw.stats();
+    assert_eq!(stats.layers, 1);
+    assert_eq!(stats.layer_lens, vec![0]);
+    assert_eq!(stats.zero_min_degree, 0);
+    assert_eq!(stats.zero_max_degree, 0);
+    assert_eq!(stats.entry_point, None);
+
+    let (hnsw, _) = test_hnsw_discrete();
+    // All 8 items stay on the zero layer, where each is connected to the other 7, and the first item is the
+    // entry point since no item was promoted above it.
+    assert_eq!(
+        hnsw.stats(),
+        Stats {
+            layers: 1,
+            layer_lens: vec![8],
+            zero_min_degree: 7,
+            zero_max_degree: 7,
+            zero_mean_degree: 7.0,
+            entry_point: Some(0),
+        }
+    );
+}
+
+#[test]
+fn insert_dedup_discrete() {
+    let (mut hnsw, mut searcher) = test_hnsw_discrete();
+
+    // An exact duplicate returns the existing id without adding an item.
+    assert_eq!(hnsw.insert_dedup(0b0110, &mut searcher), 5);
+    assert_eq!(hnsw.len(), 8);
+
+    // A feature at distance 1 from existing items is still inserted.
+    let id = hnsw.insert_dedup(0b0111, &mut searcher);
+    assert_eq!(id, 8);
+    assert_eq!(hnsw.len(), 9);
+
+    // Inserting it again returns the same id.
+    assert_eq!(hnsw.insert_dedup(0b0111, &mut searcher), id);
+    assert_eq!(hnsw.len(), 9);
+}
+
+#[test]
+fn clear_keeps_capacity() {
+    let mut searcher = Searcher::default();
+    let mut hnsw: Hnsw<Hamming, u8, Pcg64, 12, 24> = Hnsw::new(Hamming);
+    let features = [
+        0b0001, 0b0010, 0b0100, 0b1000, 0b0011, 0b0110, 0b1100, 0b1001,
+    ];
+    for &feature in &features {
+        hnsw.insert(feature, &mut searcher);
+    }
+    let footprint = hnsw.memory_footprint();
+
+    hnsw.clear();
+    assert!(hnsw.is_empty());
+    assert_eq!(hnsw.memory_footprint(), footprint);
+
+    // Refilling reuses the memory without reallocating.
+    for &feature in &features {
+        hnsw.insert(feature, &mut searcher);
+    }
+    assert_eq!(hnsw.memory_footprint(), footprint);
+    for (id, feature) in features.iter().enumerate() {
+        assert_eq!(hnsw.nearest_k(feature, 1, 24, &mut searcher), [id]);
+    }
+}
+
+#[test]
+fn neighbors_discrete() {
+    let (hnsw, _) = test_hnsw_discrete();
+
+    // The zero layer is complete, so every edge has a reverse edge and every item is a neighbor of every other.
+    for item in 0..8 {
+        let neighbors = hnsw.neighbors(item, 0).unwrap();
+        assert_eq!(neighbors.len(), 7);
+        assert!(!neighbors.contains(&item));
+        for &neighbor in neighbors {
+            assert!(hnsw.neighbors(neighbor, 0).unwrap().contains(&item));
+        }
+    }
+    assert_eq!(hnsw.neighbors(8, 0), None);
+    assert_eq!(hnsw.neighbors(0, hnsw.layers()), None);
+}